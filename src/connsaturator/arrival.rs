@@ -0,0 +1,84 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::ThinkTime;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+// how the gaps between open loop requests are spread around the one the rate asks for
+#[derive(Clone, Debug, Default)]
+pub enum Arrival {
+  #[default]
+  Constant,
+  Poisson,
+  Uniform { jitter: f64 },
+  // recorded gaps scaled to a mean of 1, so the rate still sets the mean
+  Empirical { path: String, gaps: Vec<f64> },
+}
+
+impl Arrival {
+  // constant, poisson, uniform[:jitter] or empirical:file with one gap in milliseconds per line
+  pub fn parse(entry: &str) -> Result<Self, String> {
+    let (kind, argument) = match entry.split_once(':') {
+      Some((kind, argument)) => (kind, Some(argument)),
+      None => (entry, None),
+    };
+
+    match (kind.trim().to_lowercase().as_str(), argument) {
+      ("constant", None) => Ok(Arrival::Constant),
+      ("poisson", None) => Ok(Arrival::Poisson),
+      ("uniform", None) => Ok(Arrival::Uniform { jitter: 0.5 }),
+      ("uniform", Some(jitter)) => jitter.trim().parse::<f64>().ok().filter(|jitter| (0.0..=1.0).contains(jitter)).map(|jitter| Arrival::Uniform { jitter })
+        .ok_or(format!("Invalid uniform jitter {}, expected a fraction between 0 and 1", jitter)),
+      ("empirical", Some(path)) => {
+        let gaps: Vec<f64> = std::fs::read_to_string(path).map_err(|e| format!("Unable to read arrival file {}: {}", path, e))?
+          .lines()
+          .map(|line| line.trim())
+          .filter(|line| !line.is_empty() && !line.starts_with('#'))
+          .map(|line| line.parse::<f64>().ok().filter(|gap| *gap >= 0.0 && gap.is_finite()).ok_or(format!("Invalid gap {} in arrival file {}", line, path)))
+          .collect::<Result<_, _>>()?;
+        let mean = gaps.iter().sum::<f64>() / gaps.len().max(1) as f64;
+        if mean <= 0.0 {
+          return Err(format!("Arrival file {} has no gaps longer than 0 ms", path));
+        }
+        Ok(Arrival::Empirical { path: path.to_string(), gaps: gaps.iter().map(|gap| gap / mean).collect() })
+      },
+      _ => Err(format!("Invalid arrival {}, expected constant, poisson, uniform[:jitter] or empirical:file", entry)),
+    }
+  }
+
+  pub fn is_random(&self) -> bool {
+    !matches!(self, Arrival::Constant)
+  }
+
+  pub fn describe(&self) -> String {
+    match self {
+      Arrival::Constant => "evenly spaced".to_string(),
+      Arrival::Poisson => "poisson, exponential gaps".to_string(),
+      Arrival::Uniform { jitter } => format!("uniform jitter of ±{:.0}%", jitter * 100.0),
+      Arrival::Empirical { path, gaps } => format!("empirical, {} gaps from {}", gaps.len(), path),
+    }
+  }
+
+  // seconds until the next request, `mean` is the gap the current rate asks for
+  pub fn gap(&self, mean: f64, rng: &mut StdRng) -> f64 {
+    match self {
+      Arrival::Constant => mean,
+      Arrival::Poisson => -(1.0 - rng.random::<f64>()).ln() * mean,
+      Arrival::Uniform { jitter } => mean * (1.0 + jitter * rng.random_range(-1.0..=1.0)),
+      Arrival::Empirical { gaps, .. } => mean * gaps[rng.random_range(0..gaps.len())],
+    }
+  }
+}
+
+// random arrivals or think times without --seed still get one, printed so the run can be replayed
+pub fn seed(config: &mut Config) {
+  let random = config.arrival.is_random() || config.think_time.as_ref().is_some_and(ThinkTime::is_random);
+  if random && config.seed.is_none() {
+    config.seed = Some(rand::rng().random());
+  }
+}
+
+// independent generator per stream of draws, 0 for the arrivals and 1 + the virtual user for think times
+pub fn rng(config: &Config, stream: u64) -> StdRng {
+  StdRng::seed_from_u64(config.seed.unwrap_or_default().wrapping_add(stream))
+}
@@ -0,0 +1,82 @@
+use serde::Serialize;
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+// rows printed per breakdown in the console, the JSON report always has all of them
+const PRINTED_ROWS: usize = 20;
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct BreakdownEntry {
+  pub requests: u64,
+  pub successful: u64,
+  pub failed: u64,
+  pub avg_latency_ms: f64,
+  pub status_codes: HashMap<String, u64>,
+  #[serde(skip)]
+  total_latency_ms: f64,
+  #[serde(skip)]
+  latency_count: u64,
+}
+
+// results split by a request dimension (API key, proxy, ...), keyed by dimension name and then by label
+#[derive(Serialize, Clone, Debug, Default)]
+#[serde(transparent)]
+pub struct Breakdowns(BTreeMap<String, BTreeMap<String, BreakdownEntry>>);
+
+impl Breakdowns {
+  pub fn record(&mut self, name: &str, label: &str, status: &str, success: bool, latency: Option<Duration>) {
+    let entry = self.0.entry(name.to_string()).or_default().entry(label.to_string()).or_default();
+
+    entry.requests += 1;
+    if success {
+      entry.successful += 1;
+    } else {
+      entry.failed += 1;
+    }
+    *entry.status_codes.entry(status.to_string()).or_insert(0) += 1;
+
+    if let Some(latency) = latency {
+      entry.total_latency_ms += latency.as_secs_f64() * 1000.0;
+      entry.latency_count += 1;
+      entry.avg_latency_ms = entry.total_latency_ms / entry.latency_count as f64;
+    }
+  }
+
+  pub fn get(&self, name: &str) -> Option<&BTreeMap<String, BreakdownEntry>> {
+    self.0.get(name)
+  }
+
+  pub fn remove(&mut self, name: &str) {
+    self.0.remove(name);
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  pub fn print(&self) {
+    for (name, entries) in &self.0 {
+      println!("\n{} Breakdown:", name);
+      println!("{}", "-".repeat(60));
+
+      let mut rows: Vec<_> = entries.iter().collect();
+      rows.sort_by(|a, b| b.1.requests.cmp(&a.1.requests).then(a.0.cmp(b.0)));
+
+      for (label, entry) in rows.iter().take(PRINTED_ROWS) {
+        let success_rate = entry.successful as f64 * 100.0 / entry.requests as f64;
+        println!("{:<35} {} requests, {:.2}% success, {:.2} ms avg", label, entry.requests, success_rate, entry.avg_latency_ms);
+
+        let mut status_codes: Vec<_> = entry.status_codes.iter().collect();
+        status_codes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (status, count) in status_codes {
+          println!("  {:<33} {} requests", status, count);
+        }
+      }
+
+      if rows.len() > PRINTED_ROWS {
+        println!("... {} more in the JSON report", rows.len() - PRINTED_ROWS);
+      }
+    }
+  }
+}
@@ -0,0 +1,138 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::RequestContext;
+use crate::connsaturator::TestMode;
+use crate::connsaturator::net::Target;
+use crate::connsaturator::resolve;
+use crate::connsaturator::tls;
+
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, ClientBuilder, Identity, Proxy};
+
+use std::net::{IpAddr, SocketAddr};
+
+// settings that need a reqwest Client of their own
+#[derive(Clone, Debug, Default)]
+struct ClientProfile {
+  proxy: Option<(usize, String)>,
+  address: Option<SocketAddr>,
+  source: Option<IpAddr>,
+}
+
+// one Client per profile, a virtual user always goes through the same one
+#[derive(Clone)]
+pub struct ClientPool {
+  clients: Vec<(Client, ClientProfile)>,
+  proxies: usize,
+  addresses: usize,
+  sources: usize,
+}
+
+impl ClientPool {
+  pub fn new(config: &Config) -> Result<Self, String> {
+    let target = Target::parse_target(&config.url).ok();
+    let clients = profiles(config).into_iter().map(|profile| {
+      let mut builder = base_builder(config)?;
+      if let Some((_, proxy)) = &profile.proxy {
+        builder = builder.proxy(Proxy::all(proxy).map_err(|e| format!("Invalid proxy {}: {}", mask_proxy(proxy), e))?);
+      }
+      if let (Some(address), Some(target)) = (profile.address, &target) {
+        // replaces the --resolve entry of the target host with the single address of this profile
+        builder = builder.resolve(&target.host, address);
+      }
+      if let Some(source) = profile.source {
+        builder = builder.local_address(source);
+      }
+      let client = builder.build().map_err(|e| e.to_string())?;
+      Ok((client, profile))
+    }).collect::<Result<Vec<_>, String>>()?;
+
+    Ok(ClientPool { clients, proxies: config.proxy.len(), addresses: resolve::target_addrs(config).len(), sources: config.source_address.len() })
+  }
+
+  pub fn client(&self, context: &RequestContext) -> &Client {
+    &self.clients[context.virtual_user % self.clients.len()].0
+  }
+
+  // breakdown labels of the profile used by the request's virtual user
+  pub fn labels(&self, context: &RequestContext) -> Vec<(&'static str, String)> {
+    let mut labels = Vec::new();
+    let (_, profile) = &self.clients[context.virtual_user % self.clients.len()];
+
+    if let Some((index, proxy)) = &profile.proxy
+      && self.proxies > 1 {
+      labels.push(("Proxy", format!("#{} {}", index + 1, mask_proxy(proxy))));
+    }
+    if let Some(address) = profile.address
+      && self.addresses > 1 {
+      labels.push(("Backend IP", address.ip().to_string()));
+    }
+    if let Some(source) = profile.source
+      && self.sources > 1 {
+      labels.push(("Source IP", source.to_string()));
+    }
+
+    labels
+  }
+}
+
+// every combination of proxy, target address and source address, sources vary fastest so they match net::source_address
+fn profiles(config: &Config) -> Vec<ClientProfile> {
+  let mut proxies: Vec<Option<(usize, String)>> = config.proxy.iter().cloned().enumerate().map(Some).collect();
+  if proxies.is_empty() {
+    proxies.push(None);
+  }
+  let mut addresses: Vec<Option<SocketAddr>> = resolve::target_addrs(config).into_iter().map(Some).collect();
+  if addresses.is_empty() {
+    addresses.push(None);
+  }
+  let mut sources: Vec<Option<IpAddr>> = config.source_address.iter().copied().map(Some).collect();
+  if sources.is_empty() {
+    sources.push(None);
+  }
+
+  proxies.iter().flat_map(|proxy| addresses.iter().flat_map(|address| {
+    sources.iter().map(|source| ClientProfile { proxy: proxy.clone(), address: *address, source: *source })
+  })).collect()
+}
+
+// settings shared by every client of the pool
+fn base_builder(config: &Config) -> Result<ClientBuilder, String> {
+  // http mode follows redirects itself to count the hops
+  let redirect_policy = match (config.mode, config.max_redirects) {
+    (TestMode::Http, _) | (_, 0) => Policy::none(),
+    (_, max_redirects) => Policy::limited(max_redirects),
+  };
+  let mut builder = Client::builder().danger_accept_invalid_certs(config.insecure).redirect(redirect_policy);
+  if config.no_keepalive {
+    // no idle connections are kept, so every request pays for a new TCP and TLS handshake
+    builder = builder.pool_max_idle_per_host(0);
+  }
+  for entry in &config.resolve {
+    builder = builder.resolve_to_addrs(&entry.host, &entry.addrs);
+  }
+  for cert in tls::ca_bundle(config)? {
+    builder = builder.add_root_certificate(Certificate::from_der(&cert).map_err(|e| e.to_string())?);
+  }
+  if let Some(identity) = tls::client_identity(config)? {
+    let pem = identity.cert_pem() + &identity.key_pem();
+    builder = builder.identity(Identity::from_pem(pem.as_bytes()).map_err(|e| format!("Invalid client certificate: {}", e))?);
+  }
+
+  Ok(builder)
+}
+
+// proxy url without its password, for reports and errors
+pub fn mask_proxy(proxy: &str) -> String {
+  let Some((credentials, host)) = proxy.rsplit_once('@') else {
+    return proxy.to_string();
+  };
+  let (scheme, credentials) = match credentials.split_once("://") {
+    Some((scheme, credentials)) => (format!("{}://", scheme), credentials),
+    None => (String::new(), credentials),
+  };
+
+  match credentials.split_once(':') {
+    Some((user, _)) => format!("{}{}:****@{}", scheme, user, host),
+    None => proxy.to_string(),
+  }
+}
@@ -0,0 +1,139 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::CookieMode;
+use crate::connsaturator::RequestContext;
+
+use reqwest::Url;
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::{HeaderMap, HeaderValue, SET_COOKIE};
+use serde::Serialize;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct CookieReport {
+  pub mode: String,
+  pub jars: usize,
+  pub preloaded_cookies: usize,
+  pub session_cookie: String,
+  pub cookies_set_by_server: u64,
+  pub distinct_sessions: usize,
+}
+
+impl CookieReport {
+  pub fn print(&self) {
+    println!("\nCookies:");
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {} ({})", "Cookie jars:", self.jars, self.mode);
+    println!("{:<35} {}", "Preloaded cookies:", self.preloaded_cookies);
+    println!("{:<35} {}", "Session cookie:", self.session_cookie);
+    println!("{:<35} {}", "Cookies set by server:", self.cookies_set_by_server);
+    println!("{:<35} {}", "Distinct sessions issued:", self.distinct_sessions);
+  }
+}
+
+// cookie jars kept between requests, one shared by everybody or one per virtual user
+pub struct CookieJars {
+  mode: CookieMode,
+  jars: Vec<Jar>,
+  preloaded: usize,
+  session_cookie: Option<String>,
+  cookies_set: AtomicU64,
+  sessions: Mutex<HashSet<String>>,
+}
+
+impl CookieJars {
+  pub fn new(config: &Config) -> Result<Option<Self>, String> {
+    let mode = match (config.cookies, config.cookie.is_empty()) {
+      (Some(mode), _) => mode,
+      (None, false) => CookieMode::Shared,
+      (None, true) => return Ok(None),
+    };
+
+    let url = Url::parse(&config.url).map_err(|e| format!("Invalid URL {}: {}", config.url, e))?;
+    let count = match mode {
+      CookieMode::Shared => 1,
+      CookieMode::PerUser => config.virtual_users,
+    };
+
+    let jars = (0..count).map(|_| {
+      let jar = Jar::default();
+      for cookie in &config.cookie {
+        jar.add_cookie_str(cookie, &url);
+      }
+      jar
+    }).collect();
+
+    Ok(Some(CookieJars {
+      mode,
+      jars,
+      preloaded: config.cookie.len(),
+      session_cookie: config.session_cookie.clone(),
+      cookies_set: AtomicU64::new(0),
+      sessions: Mutex::new(HashSet::new()),
+    }))
+  }
+
+  // Cookie header value for a request of the given virtual user
+  pub fn cookies(&self, url: &Url, context: &RequestContext) -> Option<HeaderValue> {
+    self.jar(context).cookies(url)
+  }
+
+  pub fn store(&self, url: &Url, headers: &HeaderMap, context: &RequestContext) {
+    let mut set_cookies = headers.get_all(SET_COOKIE).iter().peekable();
+    if set_cookies.peek().is_none() {
+      return;
+    }
+
+    let mut sessions = self.sessions.lock().unwrap();
+    for value in headers.get_all(SET_COOKIE) {
+      self.cookies_set.fetch_add(1, Ordering::Relaxed);
+
+      let Some((name, value)) = value.to_str().ok().and_then(|cookie| cookie.split(';').next()).and_then(|pair| pair.split_once('=')) else {
+        continue;
+      };
+      if !value.trim().is_empty() && self.is_session_cookie(name.trim()) {
+        sessions.insert(format!("{}={}", name.trim(), value.trim()));
+      }
+    }
+
+    self.jar(context).set_cookies(&mut set_cookies, url);
+  }
+
+  pub fn report(&self) -> CookieReport {
+    CookieReport {
+      mode: match self.mode {
+        CookieMode::Shared => "shared".to_string(),
+        CookieMode::PerUser => "per user".to_string(),
+      },
+      jars: self.jars.len(),
+      preloaded_cookies: self.preloaded,
+      session_cookie: self.session_cookie.clone().unwrap_or_else(|| "*sess*, *sid".to_string()),
+      cookies_set_by_server: self.cookies_set.load(Ordering::Relaxed),
+      distinct_sessions: self.sessions.lock().unwrap().len(),
+    }
+  }
+
+  fn jar(&self, context: &RequestContext) -> &Jar {
+    &self.jars[context.virtual_user % self.jars.len()]
+  }
+
+  // without --session-cookie, names like JSESSIONID, PHPSESSID, session or connect.sid count as sessions
+  fn is_session_cookie(&self, name: &str) -> bool {
+    match &self.session_cookie {
+      Some(session_cookie) => name == session_cookie,
+      None => {
+        let name = name.to_lowercase();
+        name.contains("sess") || name.ends_with("sid")
+      },
+    }
+  }
+}
+
+impl fmt::Debug for CookieJars {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("CookieJars").field("mode", &self.mode).field("jars", &self.jars.len()).finish_non_exhaustive()
+  }
+}
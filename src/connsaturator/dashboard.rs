@@ -0,0 +1,379 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::TestMode;
+use crate::connsaturator::timeseries::{Bucket, TimeSeries};
+
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph, Row, Sparkline, Table};
+use tokio::runtime::Handle;
+use tokio::sync::{Semaphore, watch};
+use tokio::task::JoinHandle;
+
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// rolling percentiles and error rate cover this much of the latest closed intervals
+const ROLLING_WINDOW: Duration = Duration::from_secs(10);
+
+const REFRESH: Duration = Duration::from_millis(250);
+
+pub fn validate(config: &Config) -> Result<(), String> {
+  if config.dashboard && config.rolling {
+    return Err("--dashboard and --rolling both draw while the run is going, pick one".to_string());
+  }
+
+  Ok(())
+}
+
+// without a terminal to draw on, the run goes on with the usual progress bar
+pub fn is_enabled(config: &Config) -> bool {
+  config.dashboard && config.mode == TestMode::Http && std::io::stdout().is_terminal()
+}
+
+// shared between a run and whatever steers it, requests wait here while the run is paused
+pub struct Control {
+  semaphore: Arc<Semaphore>,
+  concurrency: AtomicUsize,
+  // closed loop runs have one worker per virtual user, permits beyond those would go unused
+  max_concurrency: usize,
+  // permits a shrink has yet to take out of the semaphore, a later grow cancels these first
+  shrinking: Arc<Mutex<usize>>,
+  paused: watch::Sender<bool>,
+  aborted: AtomicBool,
+  finished: AtomicBool,
+  sent: AtomicU64,
+}
+
+impl Control {
+  pub fn new(concurrency: usize, max_concurrency: usize) -> Arc<Self> {
+    let concurrency = concurrency.min(max_concurrency);
+    Arc::new(Control {
+      semaphore: Arc::new(Semaphore::new(concurrency)),
+      concurrency: AtomicUsize::new(concurrency),
+      max_concurrency,
+      shrinking: Arc::new(Mutex::new(0)),
+      paused: watch::Sender::new(false),
+      aborted: AtomicBool::new(false),
+      finished: AtomicBool::new(false),
+      sent: AtomicU64::new(0),
+    })
+  }
+
+  // permits are the requests in flight, resizing the run adds or takes some away
+  pub fn semaphore(&self) -> Arc<Semaphore> {
+    Arc::clone(&self.semaphore)
+  }
+
+  // returns right away unless the run is paused
+  pub async fn wait_resumed(&self) {
+    let mut paused = self.paused.subscribe();
+    let _ = paused.wait_for(|paused| !paused).await;
+  }
+
+  pub fn is_aborted(&self) -> bool {
+    self.aborted.load(Ordering::Relaxed)
+  }
+
+  pub fn sent(&self) {
+    self.sent.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn finish(&self) {
+    self.finished.store(true, Ordering::Relaxed);
+  }
+
+  fn is_paused(&self) -> bool {
+    *self.paused.borrow()
+  }
+
+  fn set_paused(&self, paused: bool) {
+    self.paused.send_replace(paused);
+  }
+
+  // an aborted run is also resumed, so paused requests can see it and stop
+  fn abort(&self) {
+    self.aborted.store(true, Ordering::Relaxed);
+    self.set_paused(false);
+  }
+
+  // lowering the concurrency waits for requests in flight to give their permits back, one at a time
+  fn resize(&self, delta: isize, runtime: &Handle) {
+    let current = self.concurrency.load(Ordering::Relaxed);
+    let target = current.saturating_add_signed(delta).clamp(1, self.max_concurrency);
+    self.concurrency.store(target, Ordering::Relaxed);
+
+    let mut shrinking = self.shrinking.lock().unwrap();
+    if target > current {
+      let cancelled = (*shrinking).min(target - current);
+      *shrinking -= cancelled;
+      self.semaphore.add_permits(target - current - cancelled);
+    } else if target < current {
+      // a single task takes the permits out, a new one only starts once the last has nothing left to take
+      let idle = *shrinking == 0;
+      *shrinking += current - target;
+      if idle {
+        runtime.spawn(shrink(Arc::clone(&self.semaphore), Arc::clone(&self.shrinking)));
+      }
+    }
+  }
+}
+
+// a permit taken after a grow cancelled the rest of the shrink goes back to the semaphore
+async fn shrink(semaphore: Arc<Semaphore>, shrinking: Arc<Mutex<usize>>) {
+  while *shrinking.lock().unwrap() > 0 {
+    let Ok(permit) = Arc::clone(&semaphore).acquire_owned().await else {
+      return;
+    };
+    let mut shrinking = shrinking.lock().unwrap();
+    if *shrinking > 0 {
+      *shrinking -= 1;
+      permit.forget();
+    }
+  }
+}
+
+// full screen view of the run, drawn from the shared time series until the run finishes
+pub fn start(config: &Config, control: Arc<Control>, timeseries: Arc<Mutex<TimeSeries>>, requests: u64, start_time: Instant) -> JoinHandle<()> {
+  let runtime = Handle::current();
+  let target = config.url.clone();
+
+  tokio::task::spawn_blocking(move || {
+    let Ok(mut terminal) = ratatui::try_init() else {
+      ratatui::restore();
+      return;
+    };
+
+    while !control.finished.load(Ordering::Relaxed) {
+      let view = View::new(&control, &timeseries.lock().unwrap(), requests, start_time);
+      let _ = terminal.draw(|frame| view.draw(frame, &target));
+
+      if !event::poll(REFRESH).unwrap_or(false) {
+        continue;
+      }
+      let Ok(Event::Key(key)) = event::read() else {
+        continue;
+      };
+      if key.kind != KeyEventKind::Press {
+        continue;
+      }
+
+      // steps of a tenth of the current concurrency, at least one request
+      let step = (control.concurrency.load(Ordering::Relaxed) / 10).max(1) as isize;
+      match key.code {
+        KeyCode::Char('p') => control.set_paused(true),
+        KeyCode::Char('r') => control.set_paused(false),
+        KeyCode::Char(' ') => control.set_paused(!control.is_paused()),
+        KeyCode::Char('+') | KeyCode::Char('=') | KeyCode::Up => control.resize(step, &runtime),
+        KeyCode::Char('-') | KeyCode::Down => control.resize(-step, &runtime),
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => control.abort(),
+        KeyCode::Char('q') | KeyCode::Esc => control.abort(),
+        _ => {},
+      }
+    }
+
+    ratatui::restore();
+  })
+}
+
+// numbers of a single frame, taken while the time series is locked
+struct View {
+  elapsed: Duration,
+  state: &'static str,
+  concurrency: usize,
+  in_flight: u64,
+  completed: u64,
+  requests: u64,
+  rps: f64,
+  error_rate: f64,
+  p50: f64,
+  p95: f64,
+  p99: f64,
+  status_codes: Vec<(String, u64)>,
+  latency_history: Vec<u64>,
+}
+
+impl View {
+  fn new(control: &Control, timeseries: &TimeSeries, requests: u64, start_time: Instant) -> Self {
+    let elapsed = start_time.elapsed();
+    let buckets = timeseries.buckets();
+    let closed = &buckets[..timeseries.closed(elapsed)];
+    let window = (ROLLING_WINDOW.as_secs_f64() / timeseries.interval().as_secs_f64()).ceil().max(1.0) as usize;
+    let recent = &closed[closed.len().saturating_sub(window)..];
+
+    let completed = buckets.iter().map(|bucket| bucket.completed).sum::<u64>();
+    let mut status_codes: HashMap<String, u64> = HashMap::new();
+    for bucket in buckets {
+      for (status, count) in &bucket.status_codes {
+        *status_codes.entry(status.clone()).or_insert(0) += count;
+      }
+    }
+    let mut status_codes: Vec<(String, u64)> = status_codes.into_iter().collect();
+    status_codes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let rolling = Bucket {
+      completed: recent.iter().map(|bucket| bucket.completed).sum(),
+      errors: recent.iter().map(|bucket| bucket.errors).sum(),
+      latencies: recent.iter().flat_map(|bucket| bucket.latencies.iter().copied()).collect(),
+      ..Default::default()
+    };
+
+    View {
+      elapsed,
+      state: match (control.is_aborted(), control.is_paused()) {
+        (true, _) => "Aborting",
+        (false, true) => "Paused",
+        (false, false) => "Running",
+      },
+      concurrency: control.concurrency.load(Ordering::Relaxed),
+      in_flight: control.sent.load(Ordering::Relaxed).saturating_sub(completed),
+      completed,
+      requests,
+      rps: closed.last().map(|bucket| bucket.completed as f64 / timeseries.interval().as_secs_f64()).unwrap_or_default(),
+      error_rate: rolling.errors as f64 * 100.0 / rolling.completed.max(1) as f64,
+      p50: rolling.percentile_ms(50),
+      p95: rolling.percentile_ms(95),
+      p99: rolling.percentile_ms(99),
+      status_codes,
+      latency_history: closed.iter().map(|bucket| bucket.percentile_ms(95).round() as u64).collect(),
+    }
+  }
+
+  fn draw(&self, frame: &mut Frame, target: &str) {
+    let [header, progress, stats, sparkline, status_codes, keys] = Layout::vertical([
+      Constraint::Length(3),
+      Constraint::Length(3),
+      Constraint::Length(6),
+      Constraint::Min(6),
+      Constraint::Min(5),
+      Constraint::Length(1),
+    ]).areas(frame.area());
+
+    let state_color = match self.state {
+      "Running" => Color::Green,
+      "Paused" => Color::Yellow,
+      _ => Color::Red,
+    };
+    frame.render_widget(
+      Paragraph::new(Line::from(format!("{}  |  {:.1} s  |  {}", target, self.elapsed.as_secs_f64(), self.state)).style(Style::default().fg(state_color)))
+        .block(Block::bordered().title(" ConnSaturatorRS ")),
+      header,
+    );
+
+    frame.render_widget(
+      Gauge::default()
+        .block(Block::bordered().title(" Progress "))
+        .gauge_style(Style::default().fg(Color::Cyan))
+        .ratio((self.completed as f64 / self.requests.max(1) as f64).min(1.0))
+        .label(format!("{}/{}", self.completed, self.requests)),
+      progress,
+    );
+
+    let [left, right] = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(stats);
+    frame.render_widget(
+      Paragraph::new(vec![
+        Line::from(format!("{:<16} {:.1} req/s", "Throughput:", self.rps)),
+        Line::from(format!("{:<16} {}", "In flight:", self.in_flight)),
+        Line::from(format!("{:<16} {}", "Concurrency:", self.concurrency)),
+        Line::from(format!("{:<16} {:.2}%", "Error rate:", self.error_rate)),
+      ]).block(Block::bordered().title(" Load ")),
+      left,
+    );
+    frame.render_widget(
+      Paragraph::new(vec![
+        Line::from(format!("{:<6} {:.2} ms", "p50:", self.p50)),
+        Line::from(format!("{:<6} {:.2} ms", "p95:", self.p95)),
+        Line::from(format!("{:<6} {:.2} ms", "p99:", self.p99)),
+      ]).block(Block::bordered().title(format!(" Latency, last {} s ", ROLLING_WINDOW.as_secs()))),
+      right,
+    );
+
+    // the most recent intervals that fit the width of the chart
+    let width = sparkline.width.saturating_sub(2) as usize;
+    let history = &self.latency_history[self.latency_history.len().saturating_sub(width)..];
+    frame.render_widget(
+      Sparkline::default()
+        .block(Block::bordered().title(format!(" p95 latency per interval, max {} ms ", history.iter().max().copied().unwrap_or_default())))
+        .style(Style::default().fg(Color::Magenta))
+        .data(history),
+      sparkline,
+    );
+
+    let rows = self.status_codes.iter().map(|(status, count)| Row::new(vec![status.clone(), count.to_string()]));
+    frame.render_widget(
+      Table::new(rows, [Constraint::Percentage(70), Constraint::Percentage(30)])
+        .header(Row::new(vec!["Status", "Responses"]).style(Style::default().fg(Color::Cyan)))
+        .block(Block::bordered().title(" Status codes ")),
+      status_codes,
+    );
+
+    frame.render_widget(Paragraph::new(" p pause  r resume  space toggle  +/- concurrency  q abort with a partial report"), keys);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // lets the shrink task take whatever permits it can
+  async fn settle() {
+    tokio::time::sleep(Duration::from_millis(20)).await;
+  }
+
+  #[tokio::test]
+  async fn shrink_waits_for_requests_in_flight() {
+    let control = Control::new(10, Semaphore::MAX_PERMITS);
+    let in_flight = control.semaphore().acquire_many_owned(10).await.unwrap();
+    control.resize(-5, &Handle::current());
+    settle().await;
+    assert_eq!(control.semaphore.available_permits(), 0);
+
+    drop(in_flight);
+    settle().await;
+    assert_eq!(control.semaphore.available_permits(), 5);
+    assert_eq!(*control.shrinking.lock().unwrap(), 0);
+  }
+
+  #[test]
+  fn concurrency_is_capped_by_the_workers() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let control = Control::new(20, 10);
+    assert_eq!((control.concurrency.load(Ordering::Relaxed), control.semaphore.available_permits()), (10, 10));
+
+    control.resize(5, runtime.handle());
+    assert_eq!((control.concurrency.load(Ordering::Relaxed), control.semaphore.available_permits()), (10, 10));
+  }
+
+  #[tokio::test]
+  async fn grow_cancels_a_pending_shrink() {
+    let control = Control::new(10, Semaphore::MAX_PERMITS);
+    let in_flight = control.semaphore().acquire_many_owned(10).await.unwrap();
+    control.resize(-5, &Handle::current());
+    control.resize(5, &Handle::current());
+    settle().await;
+
+    drop(in_flight);
+    settle().await;
+    assert_eq!(control.concurrency.load(Ordering::Relaxed), 10);
+    assert_eq!(control.semaphore.available_permits(), 10);
+  }
+
+  #[tokio::test]
+  async fn grow_nets_against_a_partial_shrink() {
+    let control = Control::new(10, Semaphore::MAX_PERMITS);
+    let in_flight = control.semaphore().acquire_many_owned(10).await.unwrap();
+    control.resize(-5, &Handle::current());
+    control.resize(3, &Handle::current());
+    control.resize(-4, &Handle::current());
+
+    drop(in_flight);
+    settle().await;
+    assert_eq!(control.concurrency.load(Ordering::Relaxed), 4);
+    assert_eq!(control.semaphore.available_permits(), 4);
+  }
+}
@@ -0,0 +1,410 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::LoadResult;
+use crate::connsaturator::RequestContext;
+use crate::connsaturator::TestMode;
+use crate::connsaturator::loadshape::Schedule;
+use crate::connsaturator::requestbuilder;
+use crate::connsaturator::tls;
+
+use futures_util::StreamExt;
+use indicatif::ProgressBar;
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor, MethodDescriptor};
+use prost_types::{FileDescriptorProto, FileDescriptorSet};
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::metadata::{MetadataKey, MetadataValue};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Code, Request, Status};
+use tonic_reflection::pb::v1::server_reflection_client::ServerReflectionClient;
+use tonic_reflection::pb::v1::server_reflection_request::MessageRequest;
+use tonic_reflection::pb::v1::server_reflection_response::MessageResponse;
+use tonic_reflection::pb::v1::ServerReflectionRequest;
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct GrpcReport {
+  pub method: String,
+  pub call_type: String,
+  pub messages_sent: u64,
+  pub messages_received: u64,
+}
+
+impl GrpcReport {
+  pub fn print(&self) {
+    println!("\ngRPC:");
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {}", "Method:", self.method);
+    println!("{:<35} {}", "Call type:", self.call_type);
+    println!("{:<35} {}", "Messages sent:", self.messages_sent);
+    println!("{:<35} {}", "Messages received:", self.messages_received);
+  }
+}
+
+struct CallOutcome {
+  latency: Option<Duration>,
+  status: Code,
+  sent: u64,
+  received: u64,
+  bytes: u64,
+}
+
+// tonic builds its own rustls config, with no way to skip certificate verification
+pub fn validate(config: &Config) -> Result<(), String> {
+  if config.mode == TestMode::Grpc && config.insecure {
+    return Err("--insecure is not supported in grpc mode, pass the self-signed certificate with --cacert instead".to_string());
+  }
+
+  Ok(())
+}
+
+// drives `requests` calls of the configured method over a single HTTP/2 channel, at most `concurrency` in flight
+pub async fn execute_calls(config: &Config, progress_bar: &ProgressBar) -> Result<LoadResult, String> {
+  let method_name = config.grpc_method.clone().ok_or("gRPC mode requires --grpc-method")?;
+
+  let mut endpoint = Endpoint::from_shared(config.url.clone()).map_err(|e| format!("Invalid gRPC endpoint: {}", e))?
+    .timeout(Duration::from_secs(config.timeout));
+  if config.url.starts_with("https") {
+    let mut tls_config = ClientTlsConfig::new().with_webpki_roots();
+    let ca_bundle = tls::ca_bundle(config)?;
+    if !ca_bundle.is_empty() {
+      tls_config = tls_config.ca_certificate(Certificate::from_pem(tls::certs_pem(&ca_bundle)));
+    }
+    if let Some(identity) = tls::client_identity(config)? {
+      tls_config = tls_config.identity(Identity::from_pem(identity.cert_pem(), identity.key_pem()));
+    }
+    endpoint = endpoint.tls_config(tls_config).map_err(|e| format!("Invalid TLS configuration: {}", e))?;
+  }
+  let channel = endpoint.connect_lazy();
+
+  let pool = match &config.proto_set {
+    Some(path) => {
+      let bytes = std::fs::read(path).map_err(|e| format!("Unable to read descriptor set {}: {}", path, e))?;
+      DescriptorPool::decode(bytes.as_slice()).map_err(|e| format!("Invalid descriptor set {}: {}", path, e))?
+    },
+    None => reflect_pool(channel.clone(), &method_name).await?,
+  };
+
+  let method = find_method(&pool, &method_name)?;
+  let messages = parse_messages(&method.input(), config.body.as_deref().unwrap_or("{}"))?;
+  if messages.len() > 1 && !method.is_client_streaming() {
+    return Err(format!("{} takes a single request message, but --body has {}", method.full_name(), messages.len()));
+  }
+  let path = PathAndQuery::from_str(&format!("/{}/{}", method.parent_service().full_name(), method.name())).map_err(|e| e.to_string())?;
+
+  let call_type = match (method.is_client_streaming(), method.is_server_streaming()) {
+    (false, false) => "unary",
+    (false, true) => "server_streaming",
+    (true, false) => "client_streaming",
+    (true, true) => "bidi_streaming",
+  };
+
+  let mut latencies = Vec::new();
+  let mut status_codes = HashMap::new();
+  let mut success_counter = 0;
+  let mut error_counter = 0;
+  let total_bytes = AtomicU64::new(0);
+  let mut report = GrpcReport { method: method.full_name().to_string(), call_type: call_type.to_string(), ..Default::default() };
+
+  let semaphore = Arc::new(Semaphore::new(config.concurrency));
+  let config = Arc::new(config.clone());
+  let messages = Arc::new(messages);
+
+  let mut schedule = Schedule::new(&config);
+
+  let start_time = Instant::now();
+
+  let mut handles = Vec::new();
+
+  for iteration in 0..config.requests {
+    // open loop: each call waits for its slot in the load shape, the concurrency still caps the calls in flight
+    if let Some(schedule) = &mut schedule {
+      tokio::time::sleep_until(tokio::time::Instant::from_std(start_time + schedule.next())).await;
+    }
+
+    let clonned_channel = channel.clone();
+    let clonned_method = method.clone();
+    let clonned_messages = Arc::clone(&messages);
+    let clonned_path = path.clone();
+    let clonned_config = Arc::clone(&config);
+    let context = RequestContext::new(iteration as u64, &config);
+    let clonned_progress_bar = progress_bar.clone();
+
+    let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
+
+    let handle = tokio::spawn(async move {
+      let _permit = permit;
+      let outcome = call(clonned_channel, &clonned_method, &clonned_messages, clonned_path, &clonned_config, &context).await;
+      clonned_progress_bar.inc(1);
+      outcome
+    });
+    handles.push(handle);
+  }
+
+  for handle in handles {
+    match handle.await {
+      Ok(outcome) => {
+        if outcome.status == Code::Ok {
+          success_counter += 1;
+        } else {
+          error_counter += 1;
+        }
+
+        if let Some(latency) = outcome.latency {
+          latencies.push(latency);
+        }
+
+        report.messages_sent += outcome.sent;
+        report.messages_received += outcome.received;
+        total_bytes.fetch_add(outcome.bytes, Ordering::Relaxed);
+
+        *status_codes.entry(status_name(outcome.status).to_string()).or_insert(0) += 1;
+      },
+      Err(_) => {
+        error_counter += 1;
+        *status_codes.entry("Panic Error".to_string()).or_insert(0) += 1;
+      }
+    }
+  }
+
+  let duration = start_time.elapsed();
+  latencies.sort();
+
+  Ok(LoadResult {
+    latencies,
+    status_codes,
+    success_counter,
+    error_counter,
+    duration,
+    total_bytes,
+    grpc: Some(report),
+    ..Default::default()
+  })
+}
+
+async fn call(channel: Channel, method: &MethodDescriptor, messages: &[DynamicMessage], path: PathAndQuery, config: &Config, context: &RequestContext) -> CallOutcome {
+  let mut grpc = tonic::client::Grpc::new(channel);
+  let codec = DynamicCodec { output: method.output() };
+  let mut outcome = CallOutcome { latency: None, status: Code::Ok, sent: messages.len() as u64, received: 0, bytes: 0 };
+
+  let request_start_time = Instant::now();
+
+  if let Err(e) = grpc.ready().await {
+    outcome.status = Status::from_error(Box::new(e)).code();
+    outcome.sent = 0;
+    return outcome;
+  }
+
+  let result = if method.is_server_streaming() {
+    let response = if method.is_client_streaming() {
+      grpc.streaming(create_request(futures_util::stream::iter(messages.to_vec()), config, context), path, codec).await
+    } else {
+      grpc.server_streaming(create_request(messages[0].clone(), config, context), path, codec).await
+    };
+
+    match response {
+      Ok(response) => {
+        let mut stream = response.into_inner();
+        loop {
+          match stream.next().await {
+            Some(Ok(message)) => {
+              outcome.received += 1;
+              outcome.bytes += message.encoded_len() as u64;
+            },
+            Some(Err(status)) => break Err(status),
+            None => break Ok(()),
+          }
+        }
+      },
+      Err(status) => Err(status),
+    }
+  } else {
+    let response = if method.is_client_streaming() {
+      grpc.client_streaming(create_request(futures_util::stream::iter(messages.to_vec()), config, context), path, codec).await
+    } else {
+      grpc.unary(create_request(messages[0].clone(), config, context), path, codec).await
+    };
+
+    response.map(|response| {
+      outcome.received += 1;
+      outcome.bytes += response.get_ref().encoded_len() as u64;
+    })
+  };
+
+  match result {
+    Ok(()) => outcome.latency = Some(request_start_time.elapsed()),
+    Err(status) => {
+      // only calls that reached the server carry a meaningful latency
+      if status.code() != Code::Unavailable {
+        outcome.latency = Some(request_start_time.elapsed());
+      }
+      outcome.status = status.code();
+    },
+  }
+
+  outcome
+}
+
+// authentication and custom headers travel as gRPC metadata
+fn create_request<T>(message: T, config: &Config, context: &RequestContext) -> Request<T> {
+  let mut request = Request::new(message);
+  request.set_timeout(Duration::from_secs(config.timeout));
+
+  for (name, value) in requestbuilder::create_headers(config, context).iter() {
+    if let (Ok(key), Ok(value)) = (MetadataKey::from_bytes(name.as_str().as_bytes()), value.to_str())
+      && let Ok(value) = MetadataValue::try_from(value) {
+      request.metadata_mut().insert(key, value);
+    }
+  }
+
+  request
+}
+
+fn find_method(pool: &DescriptorPool, method_name: &str) -> Result<MethodDescriptor, String> {
+  let (service_name, name) = split_method(method_name)?;
+
+  let service = pool.get_service_by_name(service_name).ok_or(format!("Service {} not found", service_name))?;
+  let method = service.methods().find(|method| method.name() == name).ok_or(format!("Method {} not found in {}", name, service_name))?;
+
+  Ok(method)
+}
+
+// accepts both package.Service/Method and package.Service.Method
+fn split_method(method_name: &str) -> Result<(&str, &str), String> {
+  let method_name = method_name.trim_start_matches('/');
+  method_name.rsplit_once('/')
+    .or_else(|| method_name.rsplit_once('.'))
+    .ok_or(format!("Invalid gRPC method {}, expected package.Service/Method", method_name))
+}
+
+// a JSON array is sent as a stream of messages for client streaming methods, other methods take a single message
+fn parse_messages(input: &MessageDescriptor, body: &str) -> Result<Vec<DynamicMessage>, String> {
+  let value: serde_json::Value = serde_json::from_str(body).map_err(|e| format!("Invalid JSON request: {}", e))?;
+
+  let values = match value {
+    serde_json::Value::Array(values) if !values.is_empty() => values,
+    value => vec![value],
+  };
+
+  values.into_iter()
+    .map(|value| DynamicMessage::deserialize(input.clone(), value).map_err(|e| format!("Invalid {} request: {}", input.full_name(), e)))
+    .collect()
+}
+
+async fn reflect_pool(channel: Channel, method_name: &str) -> Result<DescriptorPool, String> {
+  let (service_name, _) = split_method(method_name)?;
+  let mut client = ServerReflectionClient::new(channel);
+
+  let mut files: HashMap<String, FileDescriptorProto> = HashMap::new();
+  let mut requested: HashSet<String> = HashSet::new();
+  let mut pending = vec![MessageRequest::FileContainingSymbol(service_name.to_string())];
+
+  while let Some(message_request) = pending.pop() {
+    let request = ServerReflectionRequest { host: String::new(), message_request: Some(message_request) };
+    let mut responses = client.server_reflection_info(futures_util::stream::iter(vec![request])).await
+      .map_err(|e| format!("Server reflection failed: {}", e.message()))?
+      .into_inner();
+
+    while let Some(response) = responses.next().await {
+      let response = response.map_err(|e| format!("Server reflection failed: {}", e.message()))?;
+      match response.message_response {
+        Some(MessageResponse::FileDescriptorResponse(descriptors)) => {
+          for bytes in descriptors.file_descriptor_proto {
+            let file = FileDescriptorProto::decode(bytes.as_slice()).map_err(|e| e.to_string())?;
+            files.insert(file.name().to_string(), file);
+          }
+        },
+        Some(MessageResponse::ErrorResponse(error)) => return Err(format!("Server reflection failed: {}", error.error_message)),
+        _ => {},
+      }
+    }
+
+    // servers usually send the transitive dependencies, ask for any that are missing
+    for file in files.values() {
+      for dependency in &file.dependency {
+        if !files.contains_key(dependency) && requested.insert(dependency.clone()) {
+          pending.push(MessageRequest::FileByFilename(dependency.clone()));
+        }
+      }
+    }
+  }
+
+  DescriptorPool::from_file_descriptor_set(FileDescriptorSet { file: files.into_values().collect() })
+    .map_err(|e| format!("Invalid descriptors from server reflection: {}", e))
+}
+
+fn status_name(code: Code) -> &'static str {
+  match code {
+    Code::Ok => "OK",
+    Code::Cancelled => "CANCELLED",
+    Code::Unknown => "UNKNOWN",
+    Code::InvalidArgument => "INVALID_ARGUMENT",
+    Code::DeadlineExceeded => "DEADLINE_EXCEEDED",
+    Code::NotFound => "NOT_FOUND",
+    Code::AlreadyExists => "ALREADY_EXISTS",
+    Code::PermissionDenied => "PERMISSION_DENIED",
+    Code::ResourceExhausted => "RESOURCE_EXHAUSTED",
+    Code::FailedPrecondition => "FAILED_PRECONDITION",
+    Code::Aborted => "ABORTED",
+    Code::OutOfRange => "OUT_OF_RANGE",
+    Code::Unimplemented => "UNIMPLEMENTED",
+    Code::Internal => "INTERNAL",
+    Code::Unavailable => "UNAVAILABLE",
+    Code::DataLoss => "DATA_LOSS",
+    Code::Unauthenticated => "UNAUTHENTICATED",
+  }
+}
+
+// prost-reflect messages are encoded and decoded against the method descriptors at runtime
+#[derive(Clone)]
+struct DynamicCodec {
+  output: MessageDescriptor,
+}
+
+struct DynamicEncoder;
+
+struct DynamicDecoder {
+  output: MessageDescriptor,
+}
+
+impl Codec for DynamicCodec {
+  type Encode = DynamicMessage;
+  type Decode = DynamicMessage;
+  type Encoder = DynamicEncoder;
+  type Decoder = DynamicDecoder;
+
+  fn encoder(&mut self) -> Self::Encoder {
+    DynamicEncoder
+  }
+
+  fn decoder(&mut self) -> Self::Decoder {
+    DynamicDecoder { output: self.output.clone() }
+  }
+}
+
+impl Encoder for DynamicEncoder {
+  type Item = DynamicMessage;
+  type Error = Status;
+
+  fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
+    item.encode(dst).map_err(|e| Status::internal(e.to_string()))
+  }
+}
+
+impl Decoder for DynamicDecoder {
+  type Item = DynamicMessage;
+  type Error = Status;
+
+  fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Self::Item>, Self::Error> {
+    DynamicMessage::decode(self.output.clone(), src)
+      .map(Some)
+      .map_err(|e| Status::internal(e.to_string()))
+  }
+}
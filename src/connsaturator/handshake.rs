@@ -0,0 +1,239 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::LoadResult;
+use crate::connsaturator::RequestContext;
+use crate::connsaturator::net::{self, ConnectOptions, Target};
+use crate::connsaturator::resolve;
+use crate::connsaturator::saturator::calculate_percentiles;
+use crate::connsaturator::tcp::connect_error;
+use crate::connsaturator::tls;
+
+use indicatif::ProgressBar;
+use rustls::client::Resumption;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, HandshakeKind, ProtocolVersion};
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_rustls::TlsConnector;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// how long we wait for the server's close_notify (and any session tickets) after each handshake
+const CLOSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct HandshakeReport {
+  pub target: String,
+  pub resumption_enabled: bool,
+  pub handshakes: u64,
+  pub full_handshakes: u64,
+  pub resumed_handshakes: u64,
+  pub resumption_rate: f64,
+  pub handshakes_per_sec: f64,
+  pub avg_tcp_connect_ms: f64,
+  pub avg_handshake_ms: f64,
+  pub p50_handshake_ms: f64,
+  pub p95_handshake_ms: f64,
+  pub p99_handshake_ms: f64,
+}
+
+impl HandshakeReport {
+  pub fn print(&self) {
+    println!("\nTLS Handshakes:");
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {}", "Target:", self.target);
+    println!("{:<35} {}", "Session resumption:", if self.resumption_enabled { "enabled" } else { "disabled" });
+    println!("{:<35} {}", "Completed handshakes:", self.handshakes);
+    println!("{:<35} {}", "Full handshakes:", self.full_handshakes);
+    println!("{:<35} {}", "Resumed handshakes:", self.resumed_handshakes);
+    println!("{:<35} {:.2}%", "Resumption rate:", self.resumption_rate);
+    println!("{:<35} {:.2} handshakes/s", "Handshake rate:", self.handshakes_per_sec);
+    println!("{:<35} {:.2} ms", "Average TCP connect:", self.avg_tcp_connect_ms);
+    println!("{:<35} {:.2} ms", "Average handshake:", self.avg_handshake_ms);
+    println!("{:<35} {:.2} ms", "p50 handshake:", self.p50_handshake_ms);
+    println!("{:<35} {:.2} ms", "p95 handshake:", self.p95_handshake_ms);
+    println!("{:<35} {:.2} ms", "p99 handshake:", self.p99_handshake_ms);
+  }
+}
+
+struct HandshakeOutcome {
+  tcp_connect: Option<Duration>,
+  handshake: Option<Duration>,
+  finished_at: Option<Duration>,
+  status: String,
+  resumed: bool,
+}
+
+// performs `requests` TCP connects plus TLS handshakes, `concurrency` at a time, closing each connection right after the handshake
+pub async fn execute_handshakes(config: &Config, progress_bar: &ProgressBar) -> Result<LoadResult, String> {
+  let mut target = Target::parse_target(&config.url)?;
+  target.tls = true;
+
+  let mut tls_config = (*tls::client_config(config)?).clone();
+  if config.no_resumption {
+    tls_config.resumption = Resumption::disabled();
+  }
+  let tls_config = Arc::new(tls_config);
+  let server_name = ServerName::try_from(target.host.clone()).map_err(|e| format!("Invalid server name {}: {}", target.host, e))?;
+
+  let mut latencies = Vec::new();
+  let mut status_codes = HashMap::new();
+  let mut success_counter = 0;
+  let mut error_counter = 0;
+  let mut tcp_connects = Vec::new();
+  let mut last_finished_at = Duration::ZERO;
+  let mut report = HandshakeReport { target: target.address(), resumption_enabled: !config.no_resumption, ..Default::default() };
+
+  let semaphore = Arc::new(Semaphore::new(config.concurrency));
+  let target = Arc::new(target);
+  let timeout = Duration::from_secs(config.timeout);
+
+  let start_time = Instant::now();
+
+  let mut handles = Vec::new();
+
+  for iteration in 0..config.requests {
+    let clonned_target = Arc::clone(&target);
+    let context = RequestContext::new(iteration as u64, config);
+    let options = ConnectOptions {
+      address: resolve::address(config, &target.host, target.port, &context),
+      source: net::source_address(config, &context),
+      ..Default::default()
+    };
+    let clonned_tls_config = Arc::clone(&tls_config);
+    let clonned_server_name = server_name.clone();
+    let clonned_progress_bar = progress_bar.clone();
+
+    let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
+
+    let handle = tokio::spawn(async move {
+      let outcome = handshake(&clonned_target, &options, clonned_tls_config, clonned_server_name, timeout, permit, start_time).await;
+      clonned_progress_bar.inc(1);
+      outcome
+    });
+    handles.push(handle);
+  }
+
+  for handle in handles {
+    match handle.await {
+      Ok(outcome) => {
+        if let Some(tcp_connect) = outcome.tcp_connect {
+          tcp_connects.push(tcp_connect);
+        }
+        if let Some(finished_at) = outcome.finished_at {
+          last_finished_at = last_finished_at.max(finished_at);
+        }
+
+        match outcome.handshake {
+          Some(handshake) => {
+            success_counter += 1;
+            latencies.push(handshake);
+            if outcome.resumed {
+              report.resumed_handshakes += 1;
+            } else {
+              report.full_handshakes += 1;
+            }
+          },
+          None => error_counter += 1,
+        }
+
+        *status_codes.entry(outcome.status).or_insert(0) += 1;
+      },
+      Err(_) => {
+        error_counter += 1;
+        *status_codes.entry("Panic Error".to_string()).or_insert(0) += 1;
+      }
+    }
+  }
+
+  let duration = start_time.elapsed();
+  latencies.sort();
+
+  report.handshakes = latencies.len() as u64;
+  if report.handshakes > 0 {
+    let percentiles = calculate_percentiles(&latencies);
+    let total: Duration = latencies.iter().sum();
+    report.resumption_rate = report.resumed_handshakes as f64 * 100.0 / report.handshakes as f64;
+    report.avg_handshake_ms = total.as_secs_f64() * 1000.0 / latencies.len() as f64;
+    report.p50_handshake_ms = percentiles["p50"];
+    report.p95_handshake_ms = percentiles["p95"];
+    report.p99_handshake_ms = percentiles["p99"];
+  }
+  // up to the last completed handshake, the close of the connections that follows is not part of the rate
+  if last_finished_at > Duration::ZERO {
+    report.handshakes_per_sec = report.handshakes as f64 / last_finished_at.as_secs_f64();
+  }
+  if !tcp_connects.is_empty() {
+    let total: Duration = tcp_connects.iter().sum();
+    report.avg_tcp_connect_ms = total.as_secs_f64() * 1000.0 / tcp_connects.len() as f64;
+  }
+
+  Ok(LoadResult {
+    latencies,
+    status_codes,
+    success_counter,
+    error_counter,
+    duration,
+    handshake: Some(report),
+    ..Default::default()
+  })
+}
+
+// the permit is given back once the handshake is done, so the close of the connection does not hold back the next one
+async fn handshake(target: &Target, options: &ConnectOptions, tls_config: Arc<ClientConfig>, server_name: ServerName<'static>, timeout: Duration, permit: OwnedSemaphorePermit, start_time: Instant) -> HandshakeOutcome {
+  let mut outcome = HandshakeOutcome { tcp_connect: None, handshake: None, finished_at: None, status: String::new(), resumed: false };
+
+  let connect_start_time = Instant::now();
+  let stream = match tokio::time::timeout(timeout, net::connect_tcp(target, options)).await {
+    Ok(Ok(stream)) => stream,
+    Ok(Err(e)) => {
+      outcome.status = connect_error(&e).to_string();
+      return outcome;
+    },
+    Err(_) => {
+      outcome.status = "Connect Timeout".to_string();
+      return outcome;
+    },
+  };
+  outcome.tcp_connect = Some(connect_start_time.elapsed());
+
+  let handshake_start_time = Instant::now();
+  let mut stream = match tokio::time::timeout(timeout, TlsConnector::from(tls_config).connect(server_name, stream)).await {
+    Ok(Ok(stream)) => stream,
+    Ok(Err(_)) => {
+      outcome.status = "TLS Handshake Error".to_string();
+      return outcome;
+    },
+    Err(_) => {
+      outcome.status = "TLS Handshake Timeout".to_string();
+      return outcome;
+    },
+  };
+  outcome.handshake = Some(handshake_start_time.elapsed());
+  outcome.finished_at = Some(start_time.elapsed());
+  drop(permit);
+
+  let (_, connection) = stream.get_ref();
+  outcome.resumed = connection.handshake_kind() == Some(HandshakeKind::Resumed);
+  let version = match connection.protocol_version() {
+    Some(ProtocolVersion::TLSv1_3) => "TLS 1.3",
+    Some(ProtocolVersion::TLSv1_2) => "TLS 1.2",
+    _ => "TLS",
+  };
+  outcome.status = format!("{} ({})", version, if outcome.resumed { "Resumed" } else { "Full" });
+
+  // a clean close also lets rustls pick up the TLS 1.3 session tickets needed for resumption
+  let _ = stream.shutdown().await;
+  let mut buffer = [0u8; 1024];
+  let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+    while let Ok(read) = stream.read(&mut buffer).await {
+      if read == 0 {
+        break;
+      }
+    }
+  }).await;
+
+  outcome
+}
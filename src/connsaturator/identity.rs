@@ -0,0 +1,241 @@
+use crate::connsaturator::Breakdowns;
+use crate::connsaturator::Config;
+use crate::connsaturator::RequestContext;
+
+use clap::ValueEnum;
+use reqwest::header::{FORWARDED, HeaderMap, HeaderName, HeaderValue};
+use serde::Serialize;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+pub const BREAKDOWN: &str = "Client IP";
+
+// headers carrying the synthetic client ip
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq)]
+pub enum IdentityHeader {
+  /// X-Forwarded-For: <ip>
+  XForwardedFor,
+  /// X-Real-IP: <ip>
+  XRealIp,
+  /// Forwarded: for=<ip>
+  Forwarded,
+}
+
+// CIDR range synthetic client ips are taken from, one per virtual user
+#[derive(Clone, Debug)]
+pub struct ClientIpRange {
+  pub cidr: String,
+  network: IpAddr,
+  prefix: u8,
+}
+
+impl ClientIpRange {
+  pub fn parse(cidr: &str) -> Result<Self, String> {
+    let (network, prefix) = cidr.split_once('/').ok_or("Invalid client ip range format, expected CIDR like 10.0.0.0/16")?;
+    let network = network.parse::<IpAddr>().map_err(|_| format!("Invalid client ip range address {}", network))?;
+    let bits = if network.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.parse::<u8>().ok().filter(|prefix| *prefix <= bits).ok_or(format!("Invalid client ip range prefix {}", prefix))?;
+
+    Ok(ClientIpRange { cidr: cidr.to_string(), network, prefix })
+  }
+
+  // the network and broadcast addresses are skipped when the range is big enough
+  pub fn ip(&self, virtual_user: usize) -> IpAddr {
+    let host_bits = match self.network {
+      IpAddr::V4(_) => 32 - self.prefix as u32,
+      IpAddr::V6(_) => 128 - self.prefix as u32,
+    };
+    let size = 1u128.checked_shl(host_bits).unwrap_or(0);
+    let offset = match size {
+      0 => virtual_user as u128,
+      1 | 2 => virtual_user as u128 % size,
+      _ => 1 + virtual_user as u128 % (size - 2),
+    };
+    let mask = if host_bits >= 128 { 0 } else { u128::MAX << host_bits };
+
+    match self.network {
+      IpAddr::V4(network) => IpAddr::V4(Ipv4Addr::from(((u32::from(network) as u128 & mask) | offset) as u32)),
+      IpAddr::V6(network) => IpAddr::V6(Ipv6Addr::from((u128::from(network) & mask) | offset)),
+    }
+  }
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct IdentityReport {
+  pub client_ip_range: String,
+  pub headers: Vec<String>,
+  pub simulated_clients: usize,
+  pub throttled_clients: usize,
+  pub unthrottled_clients: usize,
+  pub avg_allowed_per_client: f64,
+  pub accepted_requests: u64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub expected_allowance: Option<f64>,
+  pub verdict: String,
+}
+
+impl IdentityReport {
+  // allowed vs 429 responses per simulated client, taken from the client ip breakdown
+  pub fn new(config: &Config, breakdowns: &Breakdowns, duration: Duration) -> Option<Self> {
+    let range = config.client_ip_range.as_ref()?;
+    let mut report = IdentityReport {
+      client_ip_range: range.cidr.clone(),
+      headers: identity_headers(config).into_iter().map(|header| display_name(header).to_string()).collect(),
+      ..Default::default()
+    };
+    let Some(clients) = breakdowns.get(BREAKDOWN) else {
+      return Some(report);
+    };
+
+    for entry in clients.values() {
+      // errors and 5xx responses were not let through by the limiter, only successes count as accepted
+      report.accepted_requests += entry.successful;
+      let throttled = entry.status_codes.iter().filter(|(status, _)| status.starts_with("429")).map(|(_, count)| count).sum::<u64>();
+      if throttled > 0 {
+        report.throttled_clients += 1;
+      } else {
+        report.unthrottled_clients += 1;
+      }
+    }
+    report.simulated_clients = clients.len();
+    report.avg_allowed_per_client = report.accepted_requests as f64 / clients.len().max(1) as f64;
+
+    // requests a single limit lets through over the run, at least one full window
+    report.expected_allowance = config.expected_limit.as_ref()
+      .map(|limit| limit.requests as f64 * (duration.as_secs_f64() / limit.window.as_secs_f64()).max(1.0));
+    report.verdict = verdict(report.throttled_clients, report.simulated_clients, report.accepted_requests, report.expected_allowance);
+
+    Some(report)
+  }
+
+  pub fn print(&self) {
+    println!("\nClient Identities:");
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {} ({})", "Client IP range:", self.client_ip_range, self.headers.join(", "));
+    println!("{:<35} {}", "Simulated clients:", self.simulated_clients);
+    println!("{:<35} {}", "Throttled clients (429):", self.throttled_clients);
+    println!("{:<35} {}", "Never throttled clients:", self.unthrottled_clients);
+    println!("{:<35} {:.2}", "Avg allowed requests per client:", self.avg_allowed_per_client);
+    match self.expected_allowance {
+      Some(allowance) => println!("{:<35} {} (one limit allows about {:.0})", "Accepted requests (successful):", self.accepted_requests, allowance),
+      None => println!("{:<35} {}", "Accepted requests (successful):", self.accepted_requests),
+    }
+    println!("{:<35} {}", "Verdict:", self.verdict);
+  }
+}
+
+// which clients got a 429 says little on its own, a global limiter that fills up halfway throttles the late clients only
+// the accepted total tells them apart: about one limit in total is a global limiter, about one limit per client trusts the headers
+fn verdict(throttled_clients: usize, clients: usize, accepted: u64, allowance: Option<f64>) -> String {
+  if throttled_clients == 0 {
+    return "no 429 responses, the limit was not reached".to_string();
+  }
+  let Some(allowance) = allowance else {
+    return "inconclusive, pass --expected-limit or compare with a run without --client-ip-range".to_string();
+  };
+
+  let accepted = accepted as f64;
+  let global = accepted <= allowance * 1.5;
+  let per_client = clients > 1 && accepted >= allowance * clients as f64 * 0.5;
+  match (global, per_client) {
+    (true, false) => format!("about one limit in total ({:.0} allowed), the limiter ignores the spoofed headers", allowance),
+    (false, true) => format!("about one limit per client ({:.0} allowed each), the limiter trusts the spoofed headers", allowance),
+    _ => "inconclusive, compare with a run without --client-ip-range".to_string(),
+  }
+}
+
+// identity headers of the request's virtual user
+pub fn headers(config: &Config, context: &RequestContext) -> HeaderMap {
+  let mut headers = HeaderMap::new();
+  let Some(range) = &config.client_ip_range else {
+    return headers;
+  };
+
+  let ip = range.ip(context.virtual_user);
+  for header in identity_headers(config) {
+    let value = match (header, ip) {
+      (IdentityHeader::Forwarded, IpAddr::V6(ip)) => format!("for=\"[{}]\"", ip),
+      (IdentityHeader::Forwarded, ip) => format!("for={}", ip),
+      (_, ip) => ip.to_string(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+      headers.insert(header_name(header), value);
+    }
+  }
+
+  headers
+}
+
+// every header when none was picked
+fn identity_headers(config: &Config) -> Vec<IdentityHeader> {
+  if config.client_ip_header.is_empty() {
+    return vec![IdentityHeader::XForwardedFor, IdentityHeader::XRealIp, IdentityHeader::Forwarded];
+  }
+
+  config.client_ip_header.clone()
+}
+
+fn display_name(header: IdentityHeader) -> &'static str {
+  match header {
+    IdentityHeader::XForwardedFor => "X-Forwarded-For",
+    IdentityHeader::XRealIp => "X-Real-IP",
+    IdentityHeader::Forwarded => "Forwarded",
+  }
+}
+
+fn header_name(header: IdentityHeader) -> HeaderName {
+  match header {
+    IdentityHeader::XForwardedFor => HeaderName::from_static("x-forwarded-for"),
+    IdentityHeader::XRealIp => HeaderName::from_static("x-real-ip"),
+    IdentityHeader::Forwarded => FORWARDED,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn verdict_needs_throttling_and_an_expected_limit() {
+    assert_eq!(verdict(0, 10, 500, Some(100.0)), "no 429 responses, the limit was not reached");
+    assert!(verdict(4, 10, 100, None).starts_with("inconclusive"));
+  }
+
+  // a global limiter that fills up partway through throttles only the late clients
+  #[test]
+  fn verdict_global_limiter_with_mixed_clients() {
+    assert!(verdict(4, 10, 110, Some(100.0)).contains("ignores the spoofed headers"));
+  }
+
+  #[test]
+  fn verdict_limit_per_client() {
+    assert!(verdict(10, 10, 950, Some(100.0)).contains("trusts the spoofed headers"));
+  }
+
+  // a struggling target's errors and 5xx responses are not requests the limiter let through
+  #[test]
+  fn accepted_requests_are_only_successes() {
+    let config = crate::test_config(&["-u", "http://127.0.0.1/", "--client-ip-range", "10.0.0.0/24", "--expected-limit", "100/min"]);
+    let mut breakdowns = Breakdowns::default();
+    for client in 0..10 {
+      let label = format!("10.0.0.{}", client);
+      for (status, success, count) in [("200 OK", true, 10), ("503 Service Unavailable", false, 5), ("Network Error", false, 5), ("429 Too Many Requests", false, 1)] {
+        for _ in 0..count {
+          breakdowns.record(BREAKDOWN, &label, status, success, None);
+        }
+      }
+    }
+
+    let report = IdentityReport::new(&config, &breakdowns, Duration::from_secs(60)).unwrap();
+    assert_eq!(report.accepted_requests, 100);
+    assert!(report.verdict.contains("ignores the spoofed headers"), "{}", report.verdict);
+  }
+
+  #[test]
+  fn verdict_between_the_two_is_inconclusive() {
+    assert!(verdict(6, 10, 300, Some(100.0)).starts_with("inconclusive"));
+    // with two clients one limit in total and one per client are too close to tell apart
+    assert!(verdict(1, 2, 120, Some(100.0)).starts_with("inconclusive"));
+  }
+}
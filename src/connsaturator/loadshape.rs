@@ -0,0 +1,354 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::TestMode;
+use crate::connsaturator::arrival::{self, Arrival};
+use crate::connsaturator::timeseries::{Bucket, TimeSeries};
+
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use serde::Serialize;
+
+use std::f64::consts::PI;
+use std::time::Duration;
+
+// a second after a burst counts as settled once its latency is back within this factor of the baseline, without errors
+const SETTLED_LATENCY_FACTOR: f64 = 1.5;
+
+// how the request rate changes over the run, always between --rate and --rate times --peak
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq)]
+pub enum LoadShape {
+  /// Steady --rate
+  Constant,
+  /// --peak times the rate for --burst-duration at the end of every --period
+  Burst,
+  /// A single burst of --peak times the rate at --spike-at, lasting --burst-duration
+  Spike,
+  /// Smooth wave from --rate up to --peak times it and back over every --period
+  Sine,
+  /// --rate for the first half of every --period, --peak times it for the second
+  Square,
+}
+
+// checks the load shape settings once, before any request is sent
+pub fn validate(config: &Config) -> Result<(), String> {
+  // the other modes open connections as fast as the concurrency allows
+  if (config.rate.is_some() || !matches!(config.shape, LoadShape::Constant) || config.arrival.is_random())
+    && !matches!(config.mode, TestMode::Http | TestMode::Grpc) {
+    return Err(format!("--rate, --shape and --arrival are only supported in http and grpc modes, not in {:?} mode", config.mode).to_lowercase());
+  }
+
+  let Some(rate) = config.rate else {
+    return match (config.shape, config.arrival.is_random()) {
+      (LoadShape::Constant, false) => Ok(()),
+      (LoadShape::Constant, true) => Err("--arrival requires --rate".to_string()),
+      _ => Err("--shape requires --rate".to_string()),
+    };
+  };
+
+  if rate <= 0.0 || !rate.is_finite() {
+    return Err(format!("Invalid --rate {}, expected a positive number of requests per second", rate));
+  }
+  if config.peak <= 0.0 || !config.peak.is_finite() {
+    return Err(format!("Invalid --peak {}, expected a positive factor", config.peak));
+  }
+
+  match config.shape {
+    LoadShape::Burst | LoadShape::Sine | LoadShape::Square if config.period == 0 => Err("--period must be at least 1 second".to_string()),
+    LoadShape::Burst if config.burst_duration > config.period => Err(format!("--burst-duration {} s is longer than the --period of {} s", config.burst_duration, config.period)),
+    _ => Ok(()),
+  }
+}
+
+pub fn describe(config: &Config) -> Option<String> {
+  let rate = config.rate?;
+  let high = rate * config.peak;
+
+  Some(match config.shape {
+    LoadShape::Constant => format!("constant {} req/s", rate),
+    LoadShape::Burst => format!("{} req/s with {}x bursts ({} req/s) for {} s every {} s", rate, config.peak, high, config.burst_duration, config.period),
+    LoadShape::Spike => format!("{} req/s with a {}x spike ({} req/s) at {} s for {} s", rate, config.peak, high, config.spike_at, config.burst_duration),
+    LoadShape::Sine => format!("sine wave between {} and {} req/s over {} s", rate, high, config.period),
+    LoadShape::Square => format!("square wave alternating {} and {} req/s every {} s", rate, high, config.period as f64 / 2.0),
+  })
+}
+
+// open loop send times following the load shape
+#[derive(Clone, Debug)]
+pub struct Schedule {
+  arrival: Arrival,
+  rng: StdRng,
+  rate: f64,
+  peak: f64,
+  shape: LoadShape,
+  period: f64,
+  burst_duration: f64,
+  spike_at: f64,
+  next: f64,
+  gaps: u64,
+  gap_sum: f64,
+  gap_sum_squares: f64,
+}
+
+impl Schedule {
+  // None when requests are sent as fast as the concurrency allows
+  pub fn new(config: &Config) -> Option<Self> {
+    Some(Schedule {
+      arrival: config.arrival.clone(),
+      rng: arrival::rng(config, 0),
+      rate: config.rate?,
+      peak: config.peak,
+      shape: config.shape,
+      period: config.period as f64,
+      burst_duration: config.burst_duration as f64,
+      spike_at: config.spike_at as f64,
+      next: 0.0,
+      gaps: 0,
+      gap_sum: 0.0,
+      gap_sum_squares: 0.0,
+    })
+  }
+
+  // target requests per second, `secs` after the start of the run
+  pub fn rate_at(&self, secs: f64) -> f64 {
+    let high = self.rate * self.peak;
+    match self.shape {
+      LoadShape::Constant => self.rate,
+      LoadShape::Burst if secs % self.period >= self.period - self.burst_duration => high,
+      LoadShape::Spike if (self.spike_at..self.spike_at + self.burst_duration).contains(&secs) => high,
+      LoadShape::Sine => self.rate + (high - self.rate) * (1.0 - (2.0 * PI * secs / self.period).cos()) / 2.0,
+      LoadShape::Square if secs % self.period >= self.period / 2.0 => high,
+      _ => self.rate,
+    }
+  }
+
+  // time since the start of the run the next request is due, the gap after it follows the arrival distribution around the rate at that time
+  pub fn next(&mut self) -> Duration {
+    let at = self.next;
+    let gap = self.arrival.gap(1.0 / self.rate_at(at), &mut self.rng);
+    self.next += gap;
+    self.gaps += 1;
+    self.gap_sum += gap;
+    self.gap_sum_squares += gap * gap;
+    Duration::from_secs_f64(at)
+  }
+
+  // mean gap in milliseconds and its coefficient of variation, 0 for evenly spaced requests and about 1 for poisson ones
+  fn gap_stats(&self) -> (f64, f64) {
+    if self.gaps == 0 {
+      return (0.0, 0.0);
+    }
+    let mean = self.gap_sum / self.gaps as f64;
+    let variance = (self.gap_sum_squares / self.gaps as f64 - mean * mean).max(0.0);
+    (mean * 1000.0, if mean > 0.0 { variance.sqrt() / mean } else { 0.0 })
+  }
+
+  // mean target rate over `length` seconds from `start`
+  fn mean_rate(&self, start: f64, length: f64) -> f64 {
+    (0..10).map(|tenth| self.rate_at(start + length * tenth as f64 / 10.0)).sum::<f64>() / 10.0
+  }
+
+  // whether an interval belongs to a burst, the rate is closer to the peak than to the base rate
+  fn is_burst(&self, start: f64, length: f64) -> bool {
+    (self.mean_rate(start, length) - self.rate).abs() > (self.rate * self.peak - self.rate).abs() / 2.0
+  }
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct TimelineEntry {
+  pub start_secs: f64,
+  pub burst: bool,
+  pub target_rps: f64,
+  pub sent: u64,
+  pub completed: u64,
+  pub errors: u64,
+  pub throttled: u64,
+  pub avg_latency_ms: f64,
+  pub p95_latency_ms: f64,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct BurstReport {
+  pub start_secs: f64,
+  pub end_secs: f64,
+  pub target_rps: f64,
+  pub sent_rps: f64,
+  pub errors: u64,
+  pub throttled: u64,
+  pub avg_latency_ms: f64,
+  pub max_p95_latency_ms: f64,
+  // seconds into the burst until the first error or 429
+  pub first_error_secs: Option<f64>,
+  // seconds after the burst until latency and errors were back to normal
+  pub settled_secs: Option<f64>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct LoadShapeReport {
+  pub shape: String,
+  pub arrival: String,
+  pub seed: Option<u64>,
+  pub mean_gap_ms: f64,
+  pub gap_cv: f64,
+  pub baseline_latency_ms: f64,
+  pub bursts: Vec<BurstReport>,
+  pub timeline: Vec<TimelineEntry>,
+}
+
+impl LoadShapeReport {
+  // bursts lined up with the latency and errors of the intervals around them
+  pub fn new(config: &Config, schedule: Option<&Schedule>, timeseries: &TimeSeries) -> Option<Self> {
+    let schedule = schedule?;
+    let buckets = timeseries.buckets();
+    let interval = timeseries.interval().as_secs_f64();
+
+    let timeline: Vec<TimelineEntry> = buckets.iter().enumerate().map(|(index, bucket)| TimelineEntry {
+      start_secs: timeseries.start_secs(index),
+      burst: schedule.is_burst(timeseries.start_secs(index), interval),
+      target_rps: schedule.mean_rate(timeseries.start_secs(index), interval),
+      sent: bucket.sent,
+      completed: bucket.completed,
+      errors: bucket.errors,
+      throttled: bucket.throttled,
+      avg_latency_ms: bucket.avg_latency_ms(),
+      p95_latency_ms: bucket.percentile_ms(95),
+    }).collect();
+
+    // quiet intervals before the first burst, or every quiet interval when the run starts with one
+    let first_burst = timeline.iter().position(|entry| entry.burst).unwrap_or(timeline.len());
+    let quiet = if first_burst > 0 { &buckets[..first_burst] } else { buckets };
+    let baseline = Bucket {
+      latencies: quiet.iter().zip(&timeline).filter(|(_, entry)| !entry.burst).flat_map(|(bucket, _)| bucket.latencies.clone()).collect(),
+      ..Default::default()
+    };
+    let baseline_latency_ms = baseline.avg_latency_ms();
+
+    let mut bursts = Vec::new();
+    let mut index = 0;
+    while index < timeline.len() {
+      if !timeline[index].burst {
+        index += 1;
+        continue;
+      }
+      let start = index;
+      while index < timeline.len() && timeline[index].burst {
+        index += 1;
+      }
+      bursts.push(burst(&timeline[start..index], &timeline[index..], interval, baseline_latency_ms));
+    }
+
+    let (mean_gap_ms, gap_cv) = schedule.gap_stats();
+    Some(LoadShapeReport {
+      shape: describe(config)?,
+      arrival: config.arrival.describe(),
+      seed: config.seed.filter(|_| config.arrival.is_random()),
+      mean_gap_ms,
+      gap_cv,
+      baseline_latency_ms,
+      bursts,
+      timeline,
+    })
+  }
+
+  pub fn print(&self) {
+    println!("\nLoad Shape:");
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {}", "Shape:", self.shape);
+    match self.seed {
+      Some(seed) => println!("{:<35} {} (seed {})", "Arrivals:", self.arrival, seed),
+      None => println!("{:<35} {}", "Arrivals:", self.arrival),
+    }
+    println!("{:<35} {:.2} ms mean, {:.2} coefficient of variation", "Gap between requests:", self.mean_gap_ms, self.gap_cv);
+    println!("{:<35} {:.2} ms", "Baseline latency:", self.baseline_latency_ms);
+
+    for burst in &self.bursts {
+      println!("{:<35} {:.2} req/s target, {:.2} req/s sent", format!("Burst {:.1} s - {:.1} s:", burst.start_secs, burst.end_secs), burst.target_rps, burst.sent_rps);
+      println!("{:<35} {:.2} ms avg, {:.2} ms worst p95", "  Latency:", burst.avg_latency_ms, burst.max_p95_latency_ms);
+      println!("{:<35} {} ({} throttled)", "  Errors:", burst.errors, burst.throttled);
+      match burst.first_error_secs {
+        Some(secs) => println!("{:<35} {:.1} s into the burst", "  First error:", secs),
+        None => println!("{:<35} none", "  First error:"),
+      }
+      match burst.settled_secs {
+        Some(secs) => println!("{:<35} {:.1} s after the burst", "  Settled:", secs),
+        None => println!("{:<35} not before the run ended", "  Settled:"),
+      }
+    }
+
+    println!("\nTimeline:");
+    println!("{:>8} {:>10} {:>8} {:>10} {:>8} {:>6} {:>10} {:>10}", "Time s", "Target", "Sent", "Completed", "Errors", "429", "Avg ms", "p95 ms");
+    for entry in &self.timeline {
+      println!("{:>8.1} {:>10.2} {:>8} {:>10} {:>8} {:>6} {:>10.2} {:>10.2}{}",
+        entry.start_secs, entry.target_rps, entry.sent, entry.completed, entry.errors, entry.throttled, entry.avg_latency_ms, entry.p95_latency_ms,
+        if entry.burst { "  <- burst" } else { "" });
+    }
+  }
+}
+
+fn burst(intervals: &[TimelineEntry], after: &[TimelineEntry], interval: f64, baseline_latency_ms: f64) -> BurstReport {
+  let duration = intervals.len().max(1) as f64 * interval;
+  let latency_count = intervals.iter().filter(|entry| entry.avg_latency_ms > 0.0).count().max(1) as f64;
+  let start_secs = intervals.first().map(|entry| entry.start_secs).unwrap_or_default();
+
+  BurstReport {
+    start_secs,
+    end_secs: start_secs + duration,
+    target_rps: intervals.iter().map(|entry| entry.target_rps).sum::<f64>() / intervals.len().max(1) as f64,
+    sent_rps: intervals.iter().map(|entry| entry.sent).sum::<u64>() as f64 / duration,
+    errors: intervals.iter().map(|entry| entry.errors).sum(),
+    throttled: intervals.iter().map(|entry| entry.throttled).sum(),
+    avg_latency_ms: intervals.iter().map(|entry| entry.avg_latency_ms).sum::<f64>() / latency_count,
+    max_p95_latency_ms: intervals.iter().map(|entry| entry.p95_latency_ms).fold(0.0, f64::max),
+    first_error_secs: intervals.iter().position(|entry| entry.errors > 0).map(|index| index as f64 * interval),
+    settled_secs: after.iter().position(|entry| entry.completed > 0 && entry.errors == 0 && entry.avg_latency_ms <= baseline_latency_ms * SETTLED_LATENCY_FACTOR).map(|index| index as f64 * interval),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn schedule(shape: &str) -> Schedule {
+    let config = crate::test_config(&["-u", "http://127.0.0.1/", "--rate", "10", "--peak", "4", "--shape", shape, "--period", "20", "--burst-duration", "5", "--spike-at", "30"]);
+    Schedule::new(&config).unwrap()
+  }
+
+  #[test]
+  fn rate_is_rejected_outside_http_and_grpc() {
+    for mode in ["ws", "stream", "tcp", "recovery"] {
+      assert!(validate(&crate::test_config(&["-u", "http://127.0.0.1/", "--mode", mode, "--rate", "10"])).is_err(), "{}", mode);
+      assert!(validate(&crate::test_config(&["-u", "http://127.0.0.1/", "--mode", mode, "--arrival", "poisson"])).is_err(), "{}", mode);
+    }
+    assert!(validate(&crate::test_config(&["-u", "http://127.0.0.1/", "--mode", "grpc", "--rate", "10", "--shape", "burst"])).is_ok());
+  }
+
+  #[test]
+  fn burst_at_the_end_of_each_period() {
+    let schedule = schedule("burst");
+    for (secs, rate) in [(0.0, 10.0), (14.9, 10.0), (15.0, 40.0), (19.9, 40.0), (20.0, 10.0), (35.0, 40.0)] {
+      assert_eq!(schedule.rate_at(secs), rate, "{} s", secs);
+    }
+  }
+
+  #[test]
+  fn spike_only_once() {
+    let schedule = schedule("spike");
+    for (secs, rate) in [(0.0, 10.0), (29.9, 10.0), (30.0, 40.0), (34.9, 40.0), (35.0, 10.0), (50.0, 10.0)] {
+      assert_eq!(schedule.rate_at(secs), rate, "{} s", secs);
+    }
+  }
+
+  #[test]
+  fn sine_between_the_base_and_peak_rates() {
+    let schedule = schedule("sine");
+    for (secs, rate) in [(0.0, 10.0), (5.0, 25.0), (10.0, 40.0), (15.0, 25.0), (20.0, 10.0)] {
+      assert!((schedule.rate_at(secs) - rate).abs() < 1e-9, "{} s: {}", secs, schedule.rate_at(secs));
+    }
+  }
+
+  #[test]
+  fn square_high_in_the_second_half_of_each_period() {
+    let schedule = schedule("square");
+    for (secs, rate) in [(0.0, 10.0), (9.9, 10.0), (10.0, 40.0), (19.9, 40.0), (20.0, 10.0), (30.0, 40.0)] {
+      assert_eq!(schedule.rate_at(secs), rate, "{} s", secs);
+    }
+  }
+}
@@ -1,16 +1,62 @@
 
 pub mod saturator; 
 pub mod requestbuilder;
+pub mod websocket;
+pub mod stream;
+pub mod grpc;
+pub mod net;
+pub mod tcp;
+pub mod slowclient;
+pub mod handshake;
+pub mod signing;
+pub mod jwt;
+pub mod breakdown;
+pub mod cookies;
+pub mod redirect;
+pub mod tls;
+pub mod clientpool;
+pub mod resolve;
+pub mod identity;
+pub mod ratelimit;
+pub mod retry;
+pub mod recovery;
+pub mod timeseries;
+pub mod loadshape;
+pub mod arrival;
+pub mod pacing;
+pub mod dashboard;
 
 use clap::ValueEnum;
 
 pub use saturator::ConnSaturator;
-pub use requestbuilder::create_builder;
+pub use websocket::WebSocketReport;
+pub use stream::StreamReport;
+pub use grpc::GrpcReport;
+pub use tcp::TcpReport;
+pub use slowclient::SlowClientReport;
+pub use handshake::HandshakeReport;
+pub use jwt::JwtConfig;
+pub use breakdown::Breakdowns;
+pub use cookies::{CookieJars, CookieReport};
+pub use redirect::RedirectReport;
+pub use clientpool::ClientPool;
+pub use resolve::ResolveOverride;
+pub use identity::{ClientIpRange, IdentityHeader, IdentityReport};
+pub use ratelimit::{ExpectedLimit, RateLimitReport};
+pub use retry::{BackoffStrategy, RetryCondition, RetryReport};
+pub use recovery::RecoveryReport;
+pub use loadshape::{LoadShape, LoadShapeReport};
+pub use arrival::Arrival;
+pub use pacing::{PacingReport, ThinkTime};
+pub use timeseries::TimeSeries;
 
 use reqwest::header::{HeaderName, HeaderValue};
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use serde_json::{Map, Value};
 use std::str::FromStr;
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
 use std::sync::atomic::{AtomicU64};
 use std::time::Duration;
 
@@ -23,12 +69,67 @@ pub enum HttpMethods {
   Delete,
 }
 
+//Test modes
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq)]
+pub enum TestMode {
+  /// Request/response HTTP load
+  Http,
+  /// Concurrent WebSocket connections
+  Ws,
+  /// Long-lived streaming responses (SSE or chunked long-poll)
+  Stream,
+  /// gRPC calls described by a descriptor set or server reflection
+  Grpc,
+  /// Raw TCP (or TLS) connections held open while idle
+  Tcp,
+  /// Deliberately slow clients to validate server timeouts
+  Slow,
+  /// TLS handshakes only, one fresh connection each
+  Handshake,
+  /// Saturate until the target fails, then probe at a low rate to measure the time to recovery
+  Recovery,
+}
+
+//API key placements
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq)]
+pub enum ApiKeyPlacement {
+  /// Request header (Default name: X-API-Key)
+  Header,
+  /// Query parameter (Default name: api_key)
+  Query,
+  /// Cookie (Default name: api_key)
+  Cookie,
+}
+
+//Cookie jar sharing
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq)]
+pub enum CookieMode {
+  /// One jar shared by every virtual user
+  Shared,
+  /// One isolated jar per virtual user
+  PerUser,
+}
+
+//Slow client behaviours
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq)]
+pub enum SlowMode {
+  /// Send the request headers a few bytes at a time
+  Headers,
+  /// Send the headers at once and trickle the body
+  Body,
+  /// Send the request at once and read the response slowly
+  Read,
+}
+
 #[derive(Clone, Debug)]
 pub enum AuthMethods {
   Bearer(String),
   OAuth2 {config: OAuth2Config, },
-  APIKey {key: String, },
+  APIKey {keys: Vec<String>, },
   Basic {username: String, password: String, },
+  Hmac {key_id: Option<String>, secret: String, },
+  SigV4 {access_key: String, secret_key: String, session_token: Option<String>, },
+  Jwt {config: JwtConfig, },
 }
 
 impl AuthMethods {
@@ -45,7 +146,21 @@ impl AuthMethods {
     match type_token.as_str() {
       "bearer" => Ok(AuthMethods::Bearer(token)),
       "oauth2" => Ok(AuthMethods::OAuth2 {config: OAuth2Config { client_id: token.to_string(), access_token: token.to_string(), refresh_token: token.to_string(), scope: token.to_string(), token_type: token.to_string() }}),
-      "apikey" => Ok(AuthMethods::APIKey {key: token.to_string()}),
+      "apikey" => match token.strip_prefix('@') {
+        Some(path) => {
+          let keys: Vec<String> = std::fs::read_to_string(path).map_err(|e| format!("Unable to read API key file {}: {}", path, e))?
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.to_string())
+            .collect();
+          if keys.is_empty() {
+            return Err(format!("API key file {} has no keys", path));
+          }
+          Ok(AuthMethods::APIKey {keys})
+        },
+        None => Ok(AuthMethods::APIKey {keys: vec![token.to_string()]}),
+      },
       "basic" => {
         let parts: Vec<&str> = token.splitn(2, ':').collect();
         if parts.len() < 2 {
@@ -53,6 +168,22 @@ impl AuthMethods {
         }
         Ok(AuthMethods::Basic {username: parts[0].to_string(), password: parts[1].to_string()}) 
       },
+      "hmac" => {
+        let parts: Vec<&str> = token.splitn(2, ':').collect();
+        match parts.as_slice() {
+          [secret] => Ok(AuthMethods::Hmac {key_id: None, secret: secret.to_string()}),
+          [key_id, secret] => Ok(AuthMethods::Hmac {key_id: Some(key_id.to_string()), secret: secret.to_string()}),
+          _ => Err("Invalid hmac token entry format".to_string()),
+        }
+      },
+      "jwt" => Ok(AuthMethods::Jwt {config: JwtConfig::parse(&token)?}),
+      "sigv4" => {
+        let parts: Vec<&str> = token.splitn(3, ':').collect();
+        if parts.len() < 2 {
+          return Err("Invalid sigv4 token entry format".to_string());
+        }
+        Ok(AuthMethods::SigV4 {access_key: parts[0].to_string(), secret_key: parts[1].to_string(), session_token: parts.get(2).map(|token| token.to_string())})
+      },
       _ => Err("Invalid token entry format".to_string()),
     } 
 
@@ -60,6 +191,7 @@ impl AuthMethods {
 }
 
 #[derive(Clone, Debug)]
+#[allow(dead_code)]
 pub struct OAuth2Config {
   pub client_id: String,
   pub access_token: String,
@@ -105,21 +237,114 @@ pub struct Config {
   pub insecure: bool,
   pub output: bool,
   pub warmup: usize,
+  pub mode: TestMode,
+  pub hold: u64,
+  pub message: Option<String>,
+  pub message_rate: Option<f64>,
+  pub grpc_method: Option<String>,
+  pub proto_set: Option<String>,
+  pub slow_mode: SlowMode,
+  pub slow_interval: u64,
+  pub slow_chunk: usize,
+  pub no_keepalive: bool,
+  pub no_resumption: bool,
+  pub cert: Option<String>,
+  pub key: Option<String>,
+  pub cert_password: Option<String>,
+  pub cacert: Option<String>,
+  pub sign_header: String,
+  pub sign_template: String,
+  pub aws_region: Option<String>,
+  pub aws_service: String,
+  pub virtual_users: usize,
+  pub jwt_claims: Map<String, Value>,
+  pub jwt_expiry: u64,
+  pub jwt_subject: String,
+  pub api_key_in: ApiKeyPlacement,
+  pub api_key_name: Option<String>,
+  pub cookies: Option<CookieMode>,
+  pub cookie: Vec<String>,
+  pub session_cookie: Option<String>,
+  pub cookie_jars: Option<Arc<CookieJars>>,
+  pub max_redirects: usize,
+  pub proxy: Vec<String>,
+  pub resolve: Vec<ResolveOverride>,
+  pub all_ips: bool,
+  pub source_address: Vec<IpAddr>,
+  pub client_ip_range: Option<ClientIpRange>,
+  pub client_ip_header: Vec<IdentityHeader>,
+  pub expected_limit: Option<ExpectedLimit>,
+  pub polite: bool,
+  pub retries: Option<usize>,
+  pub retry_on: Vec<RetryCondition>,
+  pub backoff: BackoffStrategy,
+  pub backoff_base: u64,
+  pub backoff_max: u64,
+  pub retry_after_max: Option<u64>,
+  pub failure_threshold: f64,
+  pub probe_rate: f64,
+  pub recovery_window: u64,
+  pub recovery_timeout: u64,
+  pub rate: Option<f64>,
+  pub shape: LoadShape,
+  pub peak: f64,
+  pub period: u64,
+  pub burst_duration: u64,
+  pub spike_at: u64,
+  pub arrival: Arrival,
+  pub seed: Option<u64>,
+  pub think_time: Option<ThinkTime>,
+  pub pacing: Option<u64>,
+  pub interval: f64,
+  pub rolling: bool,
+  pub dashboard: bool,
+}
+
+// position of a single request in the run, requests are spread round-robin across virtual users
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequestContext {
+  pub iteration: u64,
+  pub virtual_user: usize,
+}
+
+impl RequestContext {
+  pub fn new(iteration: u64, config: &Config) -> Self {
+    RequestContext { iteration, virtual_user: (iteration % config.virtual_users.max(1) as u64) as usize }
+  }
 }
 
 
-struct LoadResult {
+#[derive(Default)]
+pub(crate) struct LoadResult {
   latencies: Vec<Duration>,
   status_codes: HashMap<String, u64>,
   success_counter: usize,
   error_counter: usize,
   duration: Duration,
   total_bytes: AtomicU64,
+  websocket: Option<WebSocketReport>,
+  stream: Option<StreamReport>,
+  grpc: Option<GrpcReport>,
+  tcp: Option<TcpReport>,
+  slow_client: Option<SlowClientReport>,
+  handshake: Option<HandshakeReport>,
+  breakdowns: Breakdowns,
+  cookies: Option<CookieReport>,
+  redirects: Option<RedirectReport>,
+  identity: Option<IdentityReport>,
+  rate_limit: Option<RateLimitReport>,
+  retries: Option<RetryReport>,
+  recovery: Option<RecoveryReport>,
+  load_shape: Option<LoadShapeReport>,
+  pacing: Option<PacingReport>,
+  timeseries: Option<TimeSeries>,
+  aborted: bool,
 }
 
 #[derive(Serialize)]
 struct SummaryReport {
     command: String,
+    mode: String,
     target_url: String,
     warmup_requests: u64,
     total_requests: u64,
@@ -136,4 +361,36 @@ struct SummaryReport {
     status_code_distribution: HashMap<String, u64>,
     total_data_received_mb: f64,
     throughput_mbps: f64, 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    websocket: Option<WebSocketReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<StreamReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grpc: Option<GrpcReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tcp: Option<TcpReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slow_client: Option<SlowClientReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    handshake: Option<HandshakeReport>,
+    #[serde(skip_serializing_if = "Breakdowns::is_empty")]
+    breakdowns: Breakdowns,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cookies: Option<CookieReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirects: Option<RedirectReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    identity: Option<IdentityReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate_limit: Option<RateLimitReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retries: Option<RetryReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recovery: Option<RecoveryReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    load_shape: Option<LoadShapeReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pacing: Option<PacingReport>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    aborted: bool,
 }
@@ -0,0 +1,140 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::RequestContext;
+
+use reqwest::Url;
+use rustls::ClientConfig;
+use rustls::pki_types::ServerName;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio_rustls::TlsConnector;
+use tokio_rustls::client::TlsStream;
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+// host and port of a raw connection target, TLS for https:// and tls:// urls
+#[derive(Clone, Debug)]
+pub struct Target {
+  pub host: String,
+  pub port: u16,
+  pub tls: bool,
+}
+
+impl Target {
+  pub fn parse_target(url: &str) -> Result<Self, String> {
+    let url = if url.contains("://") { url.to_string() } else { format!("tcp://{}", url) };
+    let parsed = Url::parse(&url).map_err(|e| format!("Invalid target {}: {}", url, e))?;
+
+    let host = parsed.host_str().ok_or(format!("Invalid target {}: missing host", url))?
+      .trim_start_matches('[')
+      .trim_end_matches(']')
+      .to_string();
+
+    let tls = matches!(parsed.scheme(), "https" | "tls" | "wss");
+    let port = match (parsed.port(), parsed.scheme()) {
+      (Some(port), _) => port,
+      (None, "http") | (None, "ws") => 80,
+      (None, "https") | (None, "wss") | (None, "tls") => 443,
+      (None, _) => return Err(format!("Invalid target {}: missing port", url)),
+    };
+
+    Ok(Target { host, port, tls })
+  }
+
+  pub fn address(&self) -> String {
+    format!("{}:{}", self.host, self.port)
+  }
+}
+
+// socket level settings for raw connections
+#[derive(Clone, Debug, Default)]
+pub struct ConnectOptions {
+  pub recv_buffer_size: Option<u32>,
+  // skips DNS, set from --resolve
+  pub address: Option<SocketAddr>,
+  // local address the socket is bound to, set from --source-address
+  pub source: Option<IpAddr>,
+}
+
+pub enum Connection {
+  Plain(TcpStream),
+  Tls(Box<TlsStream<TcpStream>>),
+}
+
+// source address of the request's virtual user, round-robin over --source-address
+pub fn source_address(config: &Config, context: &RequestContext) -> Option<IpAddr> {
+  match config.source_address.len() {
+    0 => None,
+    count => Some(config.source_address[context.virtual_user % count]),
+  }
+}
+
+pub async fn connect(target: &Target, tls_config: Option<Arc<ClientConfig>>, options: &ConnectOptions) -> io::Result<Connection> {
+  let stream = connect_tcp(target, options).await?;
+
+  match tls_config {
+    Some(tls_config) if target.tls => {
+      let server_name = ServerName::try_from(target.host.clone()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+      let stream = TlsConnector::from(tls_config).connect(server_name, stream).await?;
+      Ok(Connection::Tls(Box::new(stream)))
+    },
+    _ => Ok(Connection::Plain(stream)),
+  }
+}
+
+pub async fn connect_tcp(target: &Target, options: &ConnectOptions) -> io::Result<TcpStream> {
+  let address = match options.address {
+    Some(address) => address,
+    None => tokio::net::lookup_host(target.address()).await?
+      .next()
+      .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("No address found for {}", target.host)))?,
+  };
+
+  let socket = if address.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+  if let Some(size) = options.recv_buffer_size {
+    socket.set_recv_buffer_size(size)?;
+  }
+  if let Some(source) = options.source {
+    socket.bind(SocketAddr::new(source, 0))?;
+  }
+
+  let stream = socket.connect(address).await?;
+  stream.set_nodelay(true)?;
+
+  Ok(stream)
+}
+
+impl AsyncRead for Connection {
+  fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+    match self.get_mut() {
+      Connection::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+      Connection::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+    }
+  }
+}
+
+impl AsyncWrite for Connection {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    match self.get_mut() {
+      Connection::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+      Connection::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+    }
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    match self.get_mut() {
+      Connection::Plain(stream) => Pin::new(stream).poll_flush(cx),
+      Connection::Tls(stream) => Pin::new(stream).poll_flush(cx),
+    }
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    match self.get_mut() {
+      Connection::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+      Connection::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+    }
+  }
+}
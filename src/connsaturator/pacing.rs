@@ -0,0 +1,159 @@
+use crate::connsaturator::Config;
+
+use rand::Rng;
+use rand::rngs::StdRng;
+use rand_distr::{Distribution, Exp, Normal};
+use serde::Serialize;
+
+use std::time::Duration;
+
+// pause a virtual user takes after a response before its next request
+#[derive(Clone, Debug)]
+pub enum ThinkTime {
+  Fixed(f64),
+  Uniform { min: f64, max: f64 },
+  Normal { mean: f64, std_dev: f64 },
+  Exponential { mean: f64 },
+}
+
+impl ThinkTime {
+  // milliseconds as 1000, 500-1500, normal:1000,200 or exp:1000
+  pub fn parse(entry: &str) -> Result<Self, String> {
+    let number = |value: &str| value.trim().parse::<f64>().ok().filter(|value| *value >= 0.0 && value.is_finite()).ok_or(format!("Invalid think time {}, expected milliseconds", value));
+
+    if let Some(arguments) = entry.strip_prefix("normal:") {
+      let (mean, std_dev) = arguments.split_once(',').ok_or("Invalid normal think time, expected normal:mean,std_dev")?;
+      return Ok(ThinkTime::Normal { mean: number(mean)?, std_dev: number(std_dev)? });
+    }
+    if let Some(mean) = entry.strip_prefix("exp:") {
+      return Ok(ThinkTime::Exponential { mean: number(mean)? });
+    }
+    if let Some((min, max)) = entry.split_once('-') {
+      let (min, max) = (number(min)?, number(max)?);
+      if min > max {
+        return Err(format!("Invalid think time range {}, the minimum is above the maximum", entry));
+      }
+      return Ok(ThinkTime::Uniform { min, max });
+    }
+
+    Ok(ThinkTime::Fixed(number(entry)?))
+  }
+
+  pub fn is_random(&self) -> bool {
+    !matches!(self, ThinkTime::Fixed(_))
+  }
+
+  pub fn describe(&self) -> String {
+    match self {
+      ThinkTime::Fixed(ms) => format!("{} ms", ms),
+      ThinkTime::Uniform { min, max } => format!("uniform {}-{} ms", min, max),
+      ThinkTime::Normal { mean, std_dev } => format!("normal {} ms ± {} ms", mean, std_dev),
+      ThinkTime::Exponential { mean } => format!("exponential, {} ms mean", mean),
+    }
+  }
+
+  // negative normal draws are cut to no pause at all
+  pub fn sample(&self, rng: &mut StdRng) -> Duration {
+    let ms = match self {
+      ThinkTime::Fixed(ms) => *ms,
+      ThinkTime::Uniform { min, max } => rng.random_range(*min..=*max),
+      ThinkTime::Normal { mean, std_dev } => Normal::new(*mean, *std_dev).map(|normal| normal.sample(rng)).unwrap_or(*mean),
+      ThinkTime::Exponential { mean } if *mean > 0.0 => Exp::new(1.0 / mean).map(|exp| exp.sample(rng)).unwrap_or(*mean),
+      ThinkTime::Exponential { .. } => 0.0,
+    };
+    Duration::from_secs_f64(ms.max(0.0) / 1000.0)
+  }
+}
+
+// think time and pacing turn each virtual user into a closed loop, the open loop --rate sends regardless of responses
+pub fn validate(config: &Config) -> Result<(), String> {
+  if is_paced(config) && config.rate.is_some() {
+    return Err("--think-time and --pacing pace each virtual user, they cannot be combined with --rate".to_string());
+  }
+
+  Ok(())
+}
+
+pub fn is_paced(config: &Config) -> bool {
+  config.think_time.is_some() || config.pacing.is_some()
+}
+
+pub fn describe(config: &Config) -> Option<String> {
+  let think_time = config.think_time.as_ref().map(|think_time| format!("{} think time", think_time.describe()));
+  let pacing = config.pacing.map(|pacing| format!("{} ms pacing", pacing));
+  let description = [think_time, pacing].into_iter().flatten().collect::<Vec<_>>().join(", ");
+  if description.is_empty() {
+    return None;
+  }
+
+  Some(match config.seed.filter(|_| config.think_time.as_ref().is_some_and(ThinkTime::is_random)) {
+    Some(seed) => format!("{} per virtual user (seed {})", description, seed),
+    None => format!("{} per virtual user", description),
+  })
+}
+
+// how a single virtual user spent the time between its iterations
+#[derive(Clone, Debug, Default)]
+pub struct VirtualUserPacing {
+  pub iterations: u64,
+  // iterations followed by a next one, think time and start to start averages cover only these
+  pub continued_iterations: u64,
+  pub think: Duration,
+  pub iteration_time: Duration,
+  pub paced_iterations: u64,
+  pub pacing_wait: Duration,
+  pub overrun_iterations: u64,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct PacingReport {
+  pub description: String,
+  pub virtual_users: usize,
+  pub requests: u64,
+  pub iterations: u64,
+  pub avg_think_ms: f64,
+  pub avg_iteration_ms: f64,
+  pub paced_iterations: u64,
+  pub avg_pacing_wait_ms: f64,
+  pub overrun_iterations: u64,
+  pub rate_per_user: f64,
+  pub effective_rate: f64,
+}
+
+impl PacingReport {
+  pub fn new(config: &Config, virtual_users: &[VirtualUserPacing], requests: u64, duration: Duration) -> Option<Self> {
+    let description = describe(config)?;
+    let continued_iterations = virtual_users.iter().map(|user| user.continued_iterations).sum::<u64>().max(1) as f64;
+    let paced_iterations = virtual_users.iter().map(|user| user.paced_iterations).sum::<u64>();
+    let seconds = duration.as_secs_f64().max(f64::EPSILON);
+
+    Some(PacingReport {
+      description,
+      virtual_users: virtual_users.len(),
+      requests,
+      iterations: virtual_users.iter().map(|user| user.iterations).sum(),
+      avg_think_ms: virtual_users.iter().map(|user| user.think.as_secs_f64()).sum::<f64>() * 1000.0 / continued_iterations,
+      avg_iteration_ms: virtual_users.iter().map(|user| user.iteration_time.as_secs_f64()).sum::<f64>() * 1000.0 / continued_iterations,
+      paced_iterations,
+      avg_pacing_wait_ms: virtual_users.iter().map(|user| user.pacing_wait.as_secs_f64()).sum::<f64>() * 1000.0 / paced_iterations.max(1) as f64,
+      overrun_iterations: virtual_users.iter().map(|user| user.overrun_iterations).sum(),
+      rate_per_user: requests as f64 / virtual_users.len().max(1) as f64 / seconds,
+      effective_rate: requests as f64 / seconds,
+    })
+  }
+
+  pub fn print(&self) {
+    println!("\nPacing:");
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {}", "Settings:", self.description);
+    println!("{:<35} {}", "Virtual users:", self.virtual_users);
+    println!("{:<35} {}", "Iterations:", self.iterations);
+    println!("{:<35} {:.2} ms", "Average think time:", self.avg_think_ms);
+    println!("{:<35} {:.2} ms", "Average iteration (start to start):", self.avg_iteration_ms);
+    if self.paced_iterations > 0 || self.overrun_iterations > 0 {
+      println!("{:<35} {} ({:.2} ms avg wait)", "Iterations held back by pacing:", self.paced_iterations, self.avg_pacing_wait_ms);
+      println!("{:<35} {}", "Iterations longer than pacing:", self.overrun_iterations);
+    }
+    println!("{:<35} {:.2} req/s per virtual user, {:.2} req/s in total", "Effective rate:", self.rate_per_user, self.effective_rate);
+  }
+}
@@ -0,0 +1,316 @@
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde::Serialize;
+use serde_json::Value;
+
+use std::time::Duration;
+
+// observed accepted rate within this fraction of the expected one counts as a match
+const TOLERANCE: f64 = 0.2;
+
+// rate limit the target is expected to enforce, as requests per window
+#[derive(Clone, Debug)]
+pub struct ExpectedLimit {
+  pub text: String,
+  pub requests: u64,
+  pub window: Duration,
+}
+
+impl ExpectedLimit {
+  // N/s, N/min, N/h or N/<seconds>s
+  pub fn parse(limit: &str) -> Result<Self, String> {
+    let (requests, window) = limit.split_once('/').ok_or("Invalid expected limit format, expected requests/window like 100/min")?;
+    let requests = requests.trim().parse::<u64>().ok().filter(|requests| *requests > 0).ok_or(format!("Invalid expected limit requests {}", requests))?;
+
+    let window = match window.trim() {
+      "s" | "sec" | "second" => 1,
+      "m" | "min" | "minute" => 60,
+      "h" | "hour" => 3600,
+      seconds => seconds.strip_suffix('s').and_then(|seconds| seconds.parse::<u64>().ok()).filter(|seconds| *seconds > 0)
+        .ok_or(format!("Invalid expected limit window {}, expected s, min, h or a number of seconds like 30s", seconds))?,
+    };
+
+    Ok(ExpectedLimit { text: limit.to_string(), requests, window: Duration::from_secs(window) })
+  }
+
+  fn rate(&self) -> f64 {
+    self.requests as f64 / self.window.as_secs_f64()
+  }
+}
+
+// rate limit information a single response carried
+#[derive(Clone, Debug, Default)]
+pub struct RateLimitSignal {
+  pub limit: Option<u64>,
+  pub remaining: Option<u64>,
+  pub retry_after_secs: Option<f64>,
+  pub policy: Option<String>,
+}
+
+impl RateLimitSignal {
+  // Retry-After, RateLimit-*, X-RateLimit-* and the combined RateLimit header
+  pub fn from_headers(headers: &HeaderMap) -> Self {
+    let header = |names: &[&str]| names.iter().find_map(|name| headers.get(*name).and_then(|value| value.to_str().ok()));
+    let combined = header(&["ratelimit"]).map(combined_fields).unwrap_or_default();
+    let field = |keys: &[&str]| combined.iter().find(|(key, _)| keys.contains(&key.as_str())).and_then(|(_, value)| leading_number(value));
+
+    RateLimitSignal {
+      limit: header(&["ratelimit-limit", "x-ratelimit-limit", "x-rate-limit-limit"]).and_then(leading_number).or(field(&["limit"])).map(|limit| limit as u64),
+      remaining: header(&["ratelimit-remaining", "x-ratelimit-remaining", "x-rate-limit-remaining"]).and_then(leading_number).or(field(&["remaining", "r"])).map(|remaining| remaining as u64),
+      retry_after_secs: headers.get(RETRY_AFTER).and_then(|value| value.to_str().ok()).and_then(retry_after),
+      policy: header(&["ratelimit-policy", "x-ratelimit-policy"]).map(|policy| policy.to_string()),
+    }
+  }
+
+  // JSON bodies like {"retry_after_seconds": 30}, used when there was no Retry-After header
+  pub fn read_body(&mut self, body: &[u8]) {
+    if self.retry_after_secs.is_some() {
+      return;
+    }
+
+    if let Ok(Value::Object(body)) = serde_json::from_slice::<Value>(body) {
+      self.retry_after_secs = ["retry_after_seconds", "retry_after", "retryAfter"].iter().find_map(|key| body.get(*key).and_then(Value::as_f64));
+    }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.limit.is_none() && self.remaining.is_none() && self.retry_after_secs.is_none() && self.policy.is_none()
+  }
+}
+
+// key=value fields of the combined header, both `limit=100, remaining=50` and `"default";r=50;t=30`
+fn combined_fields(value: &str) -> Vec<(String, String)> {
+  value.split([',', ';'])
+    .filter_map(|field| field.split_once('='))
+    .map(|(key, value)| (key.trim().to_lowercase(), value.trim().to_string()))
+    .collect()
+}
+
+fn leading_number(value: &str) -> Option<f64> {
+  let number: String = value.trim().chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+  number.parse().ok()
+}
+
+// delay in seconds or an HTTP date, negative and non finite delays are not valid
+fn retry_after(value: &str) -> Option<f64> {
+  if let Ok(seconds) = value.trim().parse::<f64>() {
+    return Some(seconds).filter(|seconds| *seconds >= 0.0 && seconds.is_finite());
+  }
+
+  let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+  Some(((date.with_timezone(&Utc) - Utc::now()).num_milliseconds() as f64 / 1000.0).max(0.0))
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct RateLimitReport {
+  pub throttled_responses: u64,
+  pub time_to_first_429_ms: Option<f64>,
+  pub accepted_before_first_429: u64,
+  pub offered_rate: f64,
+  pub accepted_rate: f64,
+  pub steady_state_offered_rate: Option<f64>,
+  pub steady_state_accepted_rate: Option<f64>,
+  pub advertised_limit: Option<u64>,
+  pub advertised_policy: Option<String>,
+  pub min_remaining: Option<u64>,
+  pub avg_retry_after_secs: Option<f64>,
+  pub max_retry_after_secs: Option<f64>,
+  pub expected_limit: Option<String>,
+  pub verdict: Option<String>,
+}
+
+impl RateLimitReport {
+  pub fn print(&self) {
+    println!("\nRate Limiting:");
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {}", "429 responses:", self.throttled_responses);
+    match self.time_to_first_429_ms {
+      Some(time) => println!("{:<35} {:.2} ms ({} accepted before)", "Time to first 429:", time, self.accepted_before_first_429),
+      None => println!("{:<35} never", "Time to first 429:"),
+    }
+    println!("{:<35} {:.2} req/s offered, {:.2} req/s accepted", "Overall rate:", self.offered_rate, self.accepted_rate);
+    if let (Some(offered), Some(accepted)) = (self.steady_state_offered_rate, self.steady_state_accepted_rate) {
+      println!("{:<35} {:.2} req/s offered, {:.2} req/s accepted", "Steady state (after first 429):", offered, accepted);
+    }
+    if let Some(limit) = self.advertised_limit {
+      println!("{:<35} {}", "Advertised limit:", limit);
+    }
+    if let Some(policy) = &self.advertised_policy {
+      println!("{:<35} {}", "Advertised policy:", policy);
+    }
+    if let Some(remaining) = self.min_remaining {
+      println!("{:<35} {}", "Lowest remaining quota:", remaining);
+    }
+    if let (Some(avg), Some(max)) = (self.avg_retry_after_secs, self.max_retry_after_secs) {
+      println!("{:<35} {:.2} s avg, {:.2} s max", "Retry-After:", avg, max);
+    }
+    if let (Some(expected), Some(verdict)) = (&self.expected_limit, &self.verdict) {
+      println!("{:<35} {}", "Expected limit:", expected);
+      println!("{:<35} {}", "Verdict:", verdict);
+    }
+  }
+}
+
+// collects every response of a run and turns them into a rate limit report
+#[derive(Default)]
+pub struct RateLimitAnalysis {
+  // completion time since the start of the run, accepted and throttled
+  responses: Vec<(Duration, bool, bool)>,
+  signals: u64,
+  advertised_limit: Option<u64>,
+  advertised_policy: Option<String>,
+  min_remaining: Option<u64>,
+  retry_afters: Vec<f64>,
+}
+
+impl RateLimitAnalysis {
+  pub fn record(&mut self, at: Duration, accepted: bool, throttled: bool, signal: &RateLimitSignal) {
+    self.responses.push((at, accepted, throttled));
+    if signal.is_empty() {
+      return;
+    }
+
+    self.signals += 1;
+    self.advertised_limit = signal.limit.or(self.advertised_limit);
+    self.advertised_policy = signal.policy.clone().or(self.advertised_policy.take());
+    if let Some(remaining) = signal.remaining {
+      self.min_remaining = Some(self.min_remaining.map_or(remaining, |min| min.min(remaining)));
+    }
+    if let Some(retry_after) = signal.retry_after_secs {
+      self.retry_afters.push(retry_after);
+    }
+  }
+
+  // None when the target showed no sign of rate limiting and no limit was expected
+  pub fn report(mut self, duration: Duration, expected: Option<&ExpectedLimit>) -> Option<RateLimitReport> {
+    let throttled = self.responses.iter().filter(|(_, _, throttled)| *throttled).count() as u64;
+    if throttled == 0 && self.signals == 0 && expected.is_none() {
+      return None;
+    }
+
+    self.responses.sort_by_key(|(at, _, _)| *at);
+    let seconds = duration.as_secs_f64().max(f64::EPSILON);
+    let accepted = self.responses.iter().filter(|(_, accepted, _)| *accepted).count() as u64;
+
+    let mut report = RateLimitReport {
+      throttled_responses: throttled,
+      offered_rate: self.responses.len() as f64 / seconds,
+      accepted_rate: accepted as f64 / seconds,
+      advertised_limit: self.advertised_limit,
+      advertised_policy: self.advertised_policy,
+      min_remaining: self.min_remaining,
+      expected_limit: expected.map(|expected| expected.text.clone()),
+      ..Default::default()
+    };
+
+    if !self.retry_afters.is_empty() {
+      report.avg_retry_after_secs = Some(self.retry_afters.iter().sum::<f64>() / self.retry_afters.len() as f64);
+      report.max_retry_after_secs = self.retry_afters.iter().cloned().reduce(f64::max);
+    }
+
+    // the steady state starts with the first 429, once the initial burst allowance is spent
+    let mut steady_window = None;
+    if let Some(first) = self.responses.iter().position(|(_, _, throttled)| *throttled) {
+      let first_at = self.responses[first].0;
+      report.time_to_first_429_ms = Some(first_at.as_secs_f64() * 1000.0);
+      report.accepted_before_first_429 = self.responses[..first].iter().filter(|(_, accepted, _)| *accepted).count() as u64;
+
+      let window = duration.saturating_sub(first_at);
+      if !window.is_zero() {
+        let steady = &self.responses[first..];
+        report.steady_state_offered_rate = Some(steady.len() as f64 / window.as_secs_f64());
+        report.steady_state_accepted_rate = Some(steady.iter().filter(|(_, accepted, _)| *accepted).count() as f64 / window.as_secs_f64());
+        steady_window = Some(window);
+      }
+    }
+
+    report.verdict = expected.map(|expected| verdict(&report, expected, accepted, duration, steady_window));
+
+    Some(report)
+  }
+}
+
+fn verdict(report: &RateLimitReport, expected: &ExpectedLimit, accepted: u64, duration: Duration, steady_window: Option<Duration>) -> String {
+  if report.throttled_responses == 0 {
+    // requests a limit of N per window could let through during the run
+    let allowance = expected.requests as f64 * (1.0 + (duration.as_secs_f64() / expected.window.as_secs_f64()).floor());
+    return if accepted as f64 > allowance * (1.0 + TOLERANCE) {
+      format!("FAIL: {} requests accepted without a 429, the expected limit allows about {:.0}", accepted, allowance)
+    } else {
+      "INCONCLUSIVE: no 429 responses, the offered load stayed within the expected limit".to_string()
+    };
+  }
+
+  // a run shorter than the window only shows the burst, a longer one shows the refill rate
+  let (observed, wanted, what) = match (steady_window, report.steady_state_accepted_rate) {
+    (Some(window), Some(rate)) if window >= expected.window => (rate, expected.rate(), "steady state accepted rate (req/s)"),
+    _ => (report.accepted_before_first_429 as f64, expected.requests as f64, "requests accepted before the first 429"),
+  };
+
+  let ratio = observed / wanted;
+  if ratio > 1.0 + TOLERANCE {
+    format!("FAIL: more permissive than expected, {} {:.2} vs {:.2}", what, observed, wanted)
+  } else if ratio < 1.0 - TOLERANCE {
+    format!("FAIL: stricter than expected, {} {:.2} vs {:.2}", what, observed, wanted)
+  } else {
+    format!("PASS: {} {:.2} vs {:.2} expected", what, observed, wanted)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use reqwest::header::HeaderValue;
+
+  #[test]
+  fn expected_limit_parses_windows() {
+    for (limit, requests, window) in [("100/min", 100, 60), ("5/s", 5, 1), ("1000/h", 1000, 3600), ("30/10s", 30, 10), (" 7 / minute ", 7, 60)] {
+      let parsed = ExpectedLimit::parse(limit).unwrap();
+      assert_eq!((parsed.requests, parsed.window), (requests, Duration::from_secs(window)), "{}", limit);
+    }
+    assert_eq!(ExpectedLimit::parse("30/10s").unwrap().rate(), 3.0);
+  }
+
+  #[test]
+  fn expected_limit_rejects_invalid_entries() {
+    for limit in ["100", "0/s", "-1/s", "abc/min", "10/fortnight", "10/0s", "10/s30"] {
+      assert!(ExpectedLimit::parse(limit).is_err(), "{}", limit);
+    }
+  }
+
+  #[test]
+  fn retry_after_seconds() {
+    assert_eq!(retry_after("120"), Some(120.0));
+    assert_eq!(retry_after(" 1.5 "), Some(1.5));
+    assert_eq!(retry_after("-5"), None);
+    assert_eq!(retry_after("NaN"), None);
+    assert_eq!(retry_after("soon"), None);
+  }
+
+  #[test]
+  fn retry_after_http_date() {
+    assert_eq!(retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(0.0));
+
+    let later = (Utc::now() + chrono::Duration::seconds(60)).to_rfc2822();
+    let delay = retry_after(&later).unwrap();
+    assert!((58.0..=60.0).contains(&delay), "{}", delay);
+  }
+
+  #[test]
+  fn signal_from_headers_and_body() {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-ratelimit-limit", HeaderValue::from_static("100"));
+    headers.insert("ratelimit", HeaderValue::from_static("\"default\";r=50;t=30"));
+    let mut signal = RateLimitSignal::from_headers(&headers);
+    assert_eq!((signal.limit, signal.remaining, signal.retry_after_secs), (Some(100), Some(50), None));
+
+    signal.read_body(br#"{"retry_after_seconds": 30}"#);
+    assert_eq!(signal.retry_after_secs, Some(30.0));
+
+    headers.insert(RETRY_AFTER, HeaderValue::from_static("5"));
+    let mut signal = RateLimitSignal::from_headers(&headers);
+    signal.read_body(br#"{"retry_after_seconds": 30}"#);
+    assert_eq!(signal.retry_after_secs, Some(5.0));
+  }
+}
@@ -0,0 +1,310 @@
+use crate::connsaturator::ClientPool;
+use crate::connsaturator::Config;
+use crate::connsaturator::LoadResult;
+use crate::connsaturator::RequestContext;
+use crate::connsaturator::redirect;
+
+use indicatif::ProgressBar;
+use serde::Serialize;
+use tokio::sync::{Semaphore, mpsc};
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+// windows with fewer requests are too small to call the failure onset
+const MIN_WINDOW_REQUESTS: u64 = 5;
+
+// a window under full load answering less than this fraction of the best one counts as a sharp drop
+const DROP_FRACTION: f64 = 0.1;
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct RecoveryReport {
+  pub failure_threshold: f64,
+  pub saturation_requests: u64,
+  pub baseline_latency_ms: f64,
+  pub first_failure_secs: Option<f64>,
+  pub failure_onset_secs: Option<f64>,
+  pub onset_reason: Option<String>,
+  pub onset_failure_rate: Option<f64>,
+  pub onset_latency_ms: Option<f64>,
+  pub degradation_window_secs: Option<f64>,
+  pub load_stopped_secs: f64,
+  pub probes: u64,
+  pub failed_probes: u64,
+  pub recovered: bool,
+  pub recovery_secs: Option<f64>,
+  pub recovery_window_secs: u64,
+}
+
+impl RecoveryReport {
+  pub fn print(&self) {
+    println!("\nRecovery:");
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {:.2}% failures in a 1 s window", "Failure threshold:", self.failure_threshold);
+    println!("{:<35} {}", "Saturation requests:", self.saturation_requests);
+    println!("{:<35} {:.2} ms", "Baseline latency (first window):", self.baseline_latency_ms);
+    match self.first_failure_secs {
+      Some(secs) => println!("{:<35} {:.2} s", "First failure:", secs),
+      None => println!("{:<35} none", "First failure:"),
+    }
+    match (self.failure_onset_secs, &self.onset_reason, self.onset_failure_rate, self.onset_latency_ms) {
+      (Some(secs), Some(reason), Some(rate), Some(latency)) => println!("{:<35} {:.2} s, {} ({:.2}% failures, {:.2} ms avg latency)", "Failure onset:", secs, reason, rate, latency),
+      _ => println!("{:<35} not reached, the target never failed", "Failure onset:"),
+    }
+    if let Some(secs) = self.degradation_window_secs {
+      println!("{:<35} {:.2} s", "Degradation window:", secs);
+    }
+    println!("{:<35} {:.2} s", "Load stopped at:", self.load_stopped_secs);
+    println!("{:<35} {} ({} failed)", "Probes:", self.probes, self.failed_probes);
+    match (self.recovered, self.recovery_secs) {
+      (true, Some(secs)) => println!("{:<35} {:.2} s after the load stopped ({} s healthy)", "Recovery time:", secs, self.recovery_window_secs),
+      _ if self.failure_onset_secs.is_some() => println!("{:<35} not recovered", "Recovery time:"),
+      _ => {},
+    }
+  }
+}
+
+// result of a single request, stamped with the time it finished since the start of the test
+struct Sample {
+  at: Duration,
+  latency: Option<Duration>,
+  status: String,
+  success: bool,
+  bytes: Option<u64>,
+}
+
+#[derive(Default)]
+struct Window {
+  requests: u64,
+  successes: u64,
+  failures: u64,
+  total_latency_ms: f64,
+  latency_count: u64,
+}
+
+impl Window {
+  fn failure_rate(&self) -> f64 {
+    self.failures as f64 * 100.0 / self.requests.max(1) as f64
+  }
+
+  fn avg_latency_ms(&self) -> f64 {
+    self.total_latency_ms / self.latency_count.max(1) as f64
+  }
+
+  fn record(windows: &mut Vec<Window>, sample: &Sample) {
+    let index = sample.at.as_secs() as usize;
+    if windows.len() <= index {
+      windows.resize_with(index + 1, Window::default);
+    }
+
+    let window = &mut windows[index];
+    window.requests += 1;
+    if sample.success {
+      window.successes += 1;
+    } else {
+      window.failures += 1;
+    }
+    if let Some(latency) = sample.latency {
+      window.total_latency_ms += latency.as_secs_f64() * 1000.0;
+      window.latency_count += 1;
+    }
+  }
+}
+
+// saturates the target until a 1 s window fails above --failure-threshold or its throughput collapses, then stops the load and probes at --probe-rate until it stays healthy for --recovery-window seconds
+pub async fn execute_recovery(clients: &ClientPool, config: &Config, progress_bar: &ProgressBar) -> LoadResult {
+  let mut result = LoadResult::default();
+  let mut windows: Vec<Window> = Vec::new();
+  let mut report = RecoveryReport {
+    failure_threshold: config.failure_threshold,
+    recovery_window_secs: config.recovery_window,
+    ..Default::default()
+  };
+
+  let semaphore = Arc::new(Semaphore::new(config.concurrency));
+  let clients = Arc::new(clients.clone());
+  let config = Arc::new(config.clone());
+  let (sender, mut receiver) = mpsc::unbounded_channel();
+
+  let start_time = Instant::now();
+
+  let mut handles = Vec::new();
+  let mut iteration = 0;
+
+  while iteration < config.requests as u64 {
+    while let Ok(sample) = receiver.try_recv() {
+      count(&mut result, &sample);
+      Window::record(&mut windows, &sample);
+    }
+    // only windows that are over can mark the onset
+    let completed = start_time.elapsed().as_secs() as usize;
+    if onset(&windows, completed, completed, config.failure_threshold).is_some() {
+      break;
+    }
+
+    let clonned_clients = Arc::clone(&clients);
+    let clonned_config = Arc::clone(&config);
+    let clonned_sender = sender.clone();
+    let clonned_progress_bar = progress_bar.clone();
+    let context = RequestContext::new(iteration, &config);
+
+    let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
+
+    let handle = tokio::spawn(async move {
+      let _permit = permit;
+      let sample = send(&clonned_clients, &clonned_config, &context, start_time).await;
+      clonned_progress_bar.inc(1);
+      let _ = clonned_sender.send(sample);
+    });
+    handles.push(handle);
+    iteration += 1;
+  }
+
+  // windows after this one only saw the in-flight requests drain, so their throughput says nothing
+  let loaded = start_time.elapsed().as_secs() as usize;
+
+  // requests already in flight still count towards the saturation phase
+  for handle in handles {
+    let _ = handle.await;
+  }
+  drop(sender);
+  while let Some(sample) = receiver.recv().await {
+    count(&mut result, &sample);
+    Window::record(&mut windows, &sample);
+  }
+
+  report.saturation_requests = iteration;
+  report.load_stopped_secs = start_time.elapsed().as_secs_f64();
+  report.baseline_latency_ms = windows.first().map(Window::avg_latency_ms).unwrap_or_default();
+  report.first_failure_secs = windows.iter().position(|window| window.failures > 0).map(|index| index as f64);
+  progress_bar.set_length(iteration);
+
+  if let Some((index, reason)) = onset(&windows, windows.len(), loaded, config.failure_threshold) {
+    report.failure_onset_secs = Some(index as f64);
+    report.onset_reason = Some(reason.to_string());
+    report.onset_failure_rate = Some(windows[index].failure_rate());
+    report.onset_latency_ms = Some(windows[index].avg_latency_ms());
+    report.degradation_window_secs = report.first_failure_secs.filter(|first| *first <= index as f64).map(|first| index as f64 - first);
+
+    progress_bar.set_message("Probing recovery");
+    probe(&clients, &config, &mut result, &mut report, iteration, start_time).await;
+  }
+
+  result.duration = start_time.elapsed();
+  result.latencies.sort();
+  result.recovery = Some(report);
+  result
+}
+
+// low rate probes until a healthy window of --recovery-window seconds or --recovery-timeout
+async fn probe(clients: &ClientPool, config: &Config, result: &mut LoadResult, report: &mut RecoveryReport, mut iteration: u64, start_time: Instant) {
+  let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / config.probe_rate.max(0.001)));
+  let recovery_window = Duration::from_secs(config.recovery_window);
+  let probe_start_time = Instant::now();
+  let mut healthy_since: Option<Duration> = None;
+
+  while probe_start_time.elapsed() < Duration::from_secs(config.recovery_timeout) {
+    interval.tick().await;
+
+    let sent_at = start_time.elapsed();
+    let context = RequestContext::new(iteration, config);
+    let sample = send(clients, config, &context, start_time).await;
+    iteration += 1;
+    report.probes += 1;
+
+    if sample.success {
+      let since = *healthy_since.get_or_insert(sent_at);
+      if sample.at.saturating_sub(since) >= recovery_window {
+        report.recovered = true;
+        report.recovery_secs = Some((since.as_secs_f64() - report.load_stopped_secs).max(0.0));
+      }
+    } else {
+      report.failed_probes += 1;
+      healthy_since = None;
+    }
+
+    count(result, &sample);
+    if report.recovered {
+      return;
+    }
+  }
+}
+
+async fn send(clients: &ClientPool, config: &Config, context: &RequestContext, start_time: Instant) -> Sample {
+  let request_start_time = Instant::now();
+  let response = redirect::send(clients.client(context), config, context).await;
+  let latency = request_start_time.elapsed();
+
+  match response {
+    Ok(followed) => Sample {
+      at: start_time.elapsed(),
+      latency: Some(latency),
+      status: followed.response.status().to_string(),
+      success: followed.response.status().is_success(),
+      bytes: followed.response.content_length(),
+    },
+    Err(_) => Sample { at: start_time.elapsed(), latency: None, status: "Network Error".to_string(), success: false, bytes: None },
+  }
+}
+
+fn count(result: &mut LoadResult, sample: &Sample) {
+  if sample.success {
+    result.success_counter += 1;
+  } else {
+    result.error_counter += 1;
+  }
+  *result.status_codes.entry(sample.status.clone()).or_insert(0) += 1;
+  if let Some(latency) = sample.latency {
+    result.latencies.push(latency);
+  }
+  if let Some(bytes) = sample.bytes {
+    result.total_bytes.fetch_add(bytes, Ordering::Relaxed);
+  }
+}
+
+// first of the `completed` windows failing above the threshold, or of the `loaded` ones whose throughput collapsed
+fn onset(windows: &[Window], completed: usize, loaded: usize, threshold: f64) -> Option<(usize, &'static str)> {
+  let mut peak = 0;
+  for (index, window) in windows.iter().enumerate().take(completed) {
+    if window.requests >= MIN_WINDOW_REQUESTS && window.failure_rate() >= threshold {
+      return Some((index, "failure rate over the threshold"));
+    }
+    if index < loaded && peak >= MIN_WINDOW_REQUESTS && (window.successes as f64) < peak as f64 * DROP_FRACTION {
+      return Some((index, "throughput dropped sharply"));
+    }
+    peak = peak.max(window.successes);
+  }
+
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn window(successes: u64, failures: u64) -> Window {
+    Window { requests: successes + failures, successes, failures, ..Default::default() }
+  }
+
+  #[test]
+  fn onset_at_the_first_window_over_the_failure_threshold() {
+    let windows = [window(10, 0), window(9, 1), window(5, 5), window(0, 10)];
+    assert_eq!(onset(&windows, 4, 4, 50.0), Some((2, "failure rate over the threshold")));
+    assert_eq!(onset(&windows, 2, 4, 50.0), None);
+  }
+
+  #[test]
+  fn onset_ignores_windows_with_too_few_requests() {
+    let windows = [window(0, 4), window(10, 0)];
+    assert_eq!(onset(&windows, 2, 2, 50.0), None);
+  }
+
+  #[test]
+  fn onset_on_a_throughput_collapse_only_under_load() {
+    let windows = [window(100, 0), window(50, 0), window(5, 0), window(0, 0)];
+    assert_eq!(onset(&windows, 4, 4, 50.0), Some((2, "throughput dropped sharply")));
+    // quiet windows after the load stops are not a collapse
+    assert_eq!(onset(&windows, 4, 2, 50.0), None);
+  }
+}
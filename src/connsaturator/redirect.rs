@@ -0,0 +1,173 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::RequestContext;
+use crate::connsaturator::requestbuilder;
+
+use reqwest::header::LOCATION;
+use reqwest::{Client, Method, Response, StatusCode, Url};
+use serde::Serialize;
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct RedirectReport {
+  pub max_redirects: usize,
+  pub redirected_requests: u64,
+  pub total_hops: u64,
+  pub avg_hops: f64,
+  pub max_hops: usize,
+  pub redirects_not_followed: u64,
+}
+
+impl RedirectReport {
+  pub fn record(&mut self, hops: usize, not_followed: bool) {
+    if hops > 0 {
+      self.redirected_requests += 1;
+      self.total_hops += hops as u64;
+      self.avg_hops = self.total_hops as f64 / self.redirected_requests as f64;
+      self.max_hops = self.max_hops.max(hops);
+    }
+    if not_followed {
+      self.redirects_not_followed += 1;
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.redirected_requests == 0 && self.redirects_not_followed == 0
+  }
+
+  pub fn print(&self) {
+    println!("\nRedirects:");
+    println!("{}", "-".repeat(60));
+    if self.max_redirects == 0 {
+      println!("{:<35} none (--no-follow)", "Redirects followed:");
+    } else {
+      println!("{:<35} up to {}", "Redirects followed:", self.max_redirects);
+    }
+    println!("{:<35} {}", "Redirected requests:", self.redirected_requests);
+    println!("{:<35} {:.2}", "Average hops per redirect chain:", self.avg_hops);
+    println!("{:<35} {}", "Max hops:", self.max_hops);
+    println!("{:<35} {}", "Redirects not followed:", self.redirects_not_followed);
+  }
+}
+
+// final response of a request and the redirect chain that led to it
+pub struct Followed {
+  pub response: Response,
+  pub hops: usize,
+  pub not_followed: bool,
+}
+
+// sends the request and follows up to `max_redirects` redirects by hand, so hops are counted and cookies are stored at every hop
+pub async fn send(client: &Client, config: &Config, context: &RequestContext) -> reqwest::Result<Followed> {
+  let mut builder = requestbuilder::create_builder(client, config, context);
+  let mut method = requestbuilder::method(config);
+  let mut hops = 0;
+  // once the chain has left the origin it never gets the credentials back, even when it stays on the new host
+  let mut crossed_origin = false;
+
+  loop {
+    let response = builder.send().await?;
+    if let Some(cookie_jars) = &config.cookie_jars {
+      cookie_jars.store(response.url(), response.headers(), context);
+    }
+
+    let Some(location) = location(&response) else {
+      return Ok(Followed { response, hops, not_followed: false });
+    };
+    if hops >= config.max_redirects {
+      return Ok(Followed { response, hops, not_followed: true });
+    }
+
+    // 307 and 308 repeat the request as is, the other redirects turn it into a GET without body
+    if !matches!(response.status(), StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT) {
+      method = Method::GET;
+    }
+    let with_body = method != Method::GET;
+    crossed_origin |= location.origin() != response.url().origin();
+
+    builder = requestbuilder::create_redirect_builder(client, config, context, &location, method.clone(), with_body, !crossed_origin);
+    hops += 1;
+  }
+}
+
+fn location(response: &Response) -> Option<Url> {
+  if !response.status().is_redirection() {
+    return None;
+  }
+
+  let location = response.headers().get(LOCATION)?.to_str().ok()?;
+  response.url().join(location).ok()
+}
+
+// scheme, host and path of the final url, without the query so login redirects group together
+pub fn final_url(response: &Response) -> String {
+  let url = response.url();
+  format!("{}{}", url.origin().ascii_serialization(), url.path())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+  use tokio::net::TcpListener;
+
+  use std::sync::{Arc, Mutex};
+
+  // path and Authorization header of every request a server got
+  type Seen = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+  // answers every request with `respond(path)`
+  async fn serve(respond: fn(&str, u16) -> String, port: u16, listener: TcpListener, seen: Seen) {
+    loop {
+      let Ok((mut stream, _)) = listener.accept().await else {
+        return;
+      };
+      let mut request = Vec::new();
+      let mut buffer = [0u8; 1024];
+      while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        match stream.read(&mut buffer).await {
+          Ok(0) | Err(_) => break,
+          Ok(read) => request.extend_from_slice(&buffer[..read]),
+        }
+      }
+      let request = String::from_utf8_lossy(&request).to_string();
+      let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+      let authorization = request.lines()
+        .find_map(|line| line.split_once(':').filter(|(name, _)| name.eq_ignore_ascii_case("authorization")).map(|(_, value)| value.trim().to_string()));
+      seen.lock().unwrap().push((path.clone(), authorization));
+      let _ = stream.write_all(respond(&path, port).as_bytes()).await;
+    }
+  }
+
+  #[tokio::test]
+  async fn credentials_stay_off_every_hop_after_leaving_the_origin() {
+    let origin = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let foreign = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let origin_port = origin.local_addr().unwrap().port();
+    let foreign_port = foreign.local_addr().unwrap().port();
+    let origin_seen: Seen = Arc::default();
+    let foreign_seen: Seen = Arc::default();
+
+    // origin -> foreign/first -> foreign/second
+    tokio::spawn(serve(|_, foreign_port| {
+      format!("HTTP/1.1 302 Found\r\nLocation: http://127.0.0.1:{}/first\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", foreign_port)
+    }, foreign_port, origin, Arc::clone(&origin_seen)));
+    tokio::spawn(serve(|path, _| match path {
+      "/first" => "HTTP/1.1 302 Found\r\nLocation: /second\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+      _ => "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_string(),
+    }, foreign_port, foreign, Arc::clone(&foreign_seen)));
+
+    let url = format!("http://127.0.0.1:{}/", origin_port);
+    let client = Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap();
+    for (arguments, authorization) in [(["--token", "Bearer secret"], "Bearer secret"), (["--header", "Authorization: Bearer header-secret"], "Bearer header-secret")] {
+      origin_seen.lock().unwrap().clear();
+      foreign_seen.lock().unwrap().clear();
+      let config = crate::test_config(&["-u", &url, arguments[0], arguments[1]]);
+      let followed = send(&client, &config, &RequestContext::new(0, &config)).await.unwrap();
+
+      assert_eq!(followed.response.status(), StatusCode::OK);
+      assert_eq!(followed.hops, 2);
+      assert_eq!(*origin_seen.lock().unwrap(), vec![("/".to_string(), Some(authorization.to_string()))]);
+      assert_eq!(*foreign_seen.lock().unwrap(), vec![("/first".to_string(), None), ("/second".to_string(), None)]);
+    }
+  }
+}
@@ -1,52 +1,193 @@
 use crate::connsaturator::HttpMethods;
 use crate::connsaturator::Config;
 use crate::connsaturator::AuthMethods;
-use crate::connsaturator::CustomHeaders;
-use crate::connsaturator::OAuth2Config;
+use crate::connsaturator::RequestContext;
+use crate::connsaturator::ApiKeyPlacement;
+use crate::connsaturator::signing;
+use crate::connsaturator::identity;
 
-use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT, CONTENT_TYPE};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::{Method, Url};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, USER_AGENT, CONTENT_TYPE};
+
+use std::str::FromStr;
 
 use std::time::Duration;
 
-pub fn create_builder(client: &reqwest::Client, config: &Config) -> reqwest::RequestBuilder {
-    let url = &config.url;
-     
-    
-    let mut builder = match config.method {
-        HttpMethods::Get => client.get(url),
-        HttpMethods::Post => client.post(url),
-        HttpMethods::Put => client.put(url),
-        HttpMethods::Delete => client.delete(url),
-    };
+pub fn create_builder(client: &reqwest::Client, config: &Config, context: &RequestContext) -> reqwest::RequestBuilder {
+    build(client, config, context, &config.url, method(config), true, true)
+}
 
-    if let Some(token) = &config.token {
+pub fn method(config: &Config) -> Method {
+    match config.method {
+        HttpMethods::Get => Method::GET,
+        HttpMethods::Post => Method::POST,
+        HttpMethods::Put => Method::PUT,
+        HttpMethods::Delete => Method::DELETE,
+    }
+}
+
+// next request of a redirect chain, credentials are only sent while the chain has not left the origin
+pub fn create_redirect_builder(client: &reqwest::Client, config: &Config, context: &RequestContext, url: &Url, method: Method, with_body: bool, same_origin: bool) -> reqwest::RequestBuilder {
+    build(client, config, context, url.as_str(), method, with_body, same_origin)
+}
+
+fn build(client: &reqwest::Client, config: &Config, context: &RequestContext, url: &str, method: Method, with_body: bool, with_credentials: bool) -> reqwest::RequestBuilder {
+    let mut builder = client.request(method.clone(), url);
+
+    let mut cookies = Vec::new();
+
+    if let Some(token) = &config.token
+        && with_credentials {
         match token {
             AuthMethods::Bearer(token) => builder = builder.bearer_auth(token),
             AuthMethods::OAuth2 { config } => builder = builder.bearer_auth(&config.access_token),
-            AuthMethods::APIKey { key } => builder = builder.header("X-API-Key", key),
+            AuthMethods::APIKey { keys } => {
+                let (_, key) = api_key(keys, context);
+                let name = api_key_name(config);
+                match config.api_key_in {
+                    ApiKeyPlacement::Header => builder = builder.header(name, key),
+                    ApiKeyPlacement::Query => builder = builder.query(&[(name, key)]),
+                    ApiKeyPlacement::Cookie => cookies.push(format!("{}={}", name, key)),
+                }
+            },
             AuthMethods::Basic { username, password } => builder = builder.basic_auth(username, Some(password)),
+            AuthMethods::Jwt { config: jwt } => builder = builder.bearer_auth(jwt.token(config, context)),
+            AuthMethods::Hmac { .. } | AuthMethods::SigV4 { .. } => {},
         }
     }
 
-    if let Some(body) = &config.body {
+    if let Some(body) = &config.body
+        && with_body {
         builder = builder.body(body.clone());
     }
 
     builder = builder.timeout(Duration::from_secs(config.timeout));
 
-    if let Some(header) = &config.header {
-        builder = builder.header(&header.name.clone(), &header.value.clone());
+    // like reqwest's own redirect policy, a custom credential header is dropped once the chain leaves the origin
+    if let Some(header) = &config.header
+        && (with_credentials || ![AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION].contains(&header.name)) {
+        builder = builder.header(header.name.clone(), header.value.clone());
     }
 
     if let Some(user_agent)= &config.user_agent {
         builder = builder.header(USER_AGENT, user_agent);
     }
 
-    if HttpMethods::Get != config.method {
+    builder = builder.headers(identity::headers(config, context));
+
+    if let Some(cookie_jars) = &config.cookie_jars
+        && let Ok(url) = Url::parse(url)
+        && let Some(Ok(jar_cookies)) = cookie_jars.cookies(&url, context).map(|value| value.to_str().map(|value| value.to_string())) {
+        cookies.push(jar_cookies);
+    }
+
+    // a single Cookie header carrying both the API key and the jar cookies
+    if !cookies.is_empty() {
+        builder = builder.header(COOKIE, cookies.join("; "));
+    }
+
+    if method != Method::GET {
         let content_type = HeaderValue::from_str(&config.content_type).unwrap_or_else(|_| HeaderValue::from_static("application/json"));
         builder = builder.header(CONTENT_TYPE, content_type);
     }
 
- 
+    // signatures cover the final method, url, headers and body, so they are computed last
+    // an invalid request keeps the original builder so the error is reported by send()
+    if let Some(AuthMethods::Hmac { .. } | AuthMethods::SigV4 { .. }) = &config.token
+        && with_credentials
+        && let Some((client, Ok(mut request))) = builder.try_clone().map(|builder| builder.build_split()) {
+        signing::sign(&mut request, config);
+        return reqwest::RequestBuilder::from_parts(client, request);
+    }
+
     builder
-}
\ No newline at end of file
+}
+
+// headers for transports that do not go through reqwest (WebSocket handshakes, raw connections)
+pub fn create_headers(config: &Config, context: &RequestContext) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    if let Some(token) = &config.token {
+        let authorization = match token {
+            AuthMethods::Bearer(token) => Some(format!("Bearer {}", token)),
+            AuthMethods::OAuth2 { config } => Some(format!("Bearer {}", config.access_token)),
+            AuthMethods::APIKey { keys } => {
+                let (_, key) = api_key(keys, context);
+                let value = match config.api_key_in {
+                    ApiKeyPlacement::Header => HeaderValue::from_str(key).ok().map(|value| (HeaderName::from_str(api_key_name(config)), value)),
+                    ApiKeyPlacement::Cookie => HeaderValue::from_str(&format!("{}={}", api_key_name(config), key)).ok().map(|value| (Ok(COOKIE), value)),
+                    ApiKeyPlacement::Query => None,
+                };
+                if let Some((Ok(name), value)) = value {
+                    headers.insert(name, value);
+                }
+                None
+            },
+            AuthMethods::Basic { username, password } => Some(format!("Basic {}", STANDARD.encode(format!("{}:{}", username, password)))),
+            AuthMethods::Jwt { config: jwt } => Some(format!("Bearer {}", jwt.token(config, context))),
+            AuthMethods::Hmac { .. } | AuthMethods::SigV4 { .. } => None,
+        };
+
+        if let Some(Ok(value)) = authorization.map(|value| HeaderValue::from_str(&value)) {
+            headers.insert(AUTHORIZATION, value);
+        }
+    }
+
+    if let Some(header) = &config.header {
+        headers.insert(header.name.clone(), header.value.clone());
+    }
+
+    if let Some(user_agent) = &config.user_agent
+        && let Ok(value) = HeaderValue::from_str(user_agent) {
+        headers.insert(USER_AGENT, value);
+    }
+
+    headers.extend(identity::headers(config, context));
+
+    headers
+}
+
+// url for transports that do not go through reqwest, with the API key appended when it travels in the query
+pub fn create_url(url: &str, config: &Config, context: &RequestContext) -> String {
+    match (&config.token, config.api_key_in, Url::parse(url)) {
+        (Some(AuthMethods::APIKey { keys }), ApiKeyPlacement::Query, Ok(mut url)) => {
+            let (_, key) = api_key(keys, context);
+            url.query_pairs_mut().append_pair(api_key_name(config), key);
+            url.to_string()
+        },
+        _ => url.to_string(),
+    }
+}
+
+// labels of the breakdowns a request is counted in, e.g. the API key it used
+pub fn breakdown_labels(config: &Config, context: &RequestContext) -> Vec<(&'static str, String)> {
+    let mut labels = Vec::new();
+
+    if let Some(AuthMethods::APIKey { keys }) = &config.token
+        && keys.len() > 1 {
+        let (index, key) = api_key(keys, context);
+        labels.push(("API Key", format!("#{} {}****", index + 1, key.chars().take(4).collect::<String>())));
+    }
+
+    if let Some(range) = &config.client_ip_range {
+        labels.push((identity::BREAKDOWN, range.ip(context.virtual_user).to_string()));
+    }
+
+    labels
+}
+
+// keys of a pool are rotated round-robin across requests
+fn api_key<'a>(keys: &'a [String], context: &RequestContext) -> (usize, &'a str) {
+    let index = (context.iteration % keys.len() as u64) as usize;
+    (index, &keys[index])
+}
+
+fn api_key_name(config: &Config) -> &str {
+    match (&config.api_key_name, config.api_key_in) {
+        (Some(name), _) => name,
+        (None, ApiKeyPlacement::Header) => "X-API-Key",
+        (None, _) => "api_key",
+    }
+}
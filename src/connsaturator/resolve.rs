@@ -0,0 +1,127 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::RequestContext;
+use crate::connsaturator::net::Target;
+
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+// curl style host:port:addr[,addr...] override, requests to host:port go to the given addresses instead of the DNS answer
+#[derive(Clone, Debug)]
+pub struct ResolveOverride {
+  pub host: String,
+  pub port: u16,
+  pub addrs: Vec<SocketAddr>,
+}
+
+impl ResolveOverride {
+  pub fn parse(entry: &str) -> Result<Self, String> {
+    let format_error = || "Invalid resolve entry format, expected host:port:addr[,addr...]".to_string();
+    // an IPv6 host is bracketed, as in curl, so its colons are not separators
+    let (host, rest) = match entry.strip_prefix('[') {
+      Some(bracketed) => bracketed.split_once("]:").ok_or_else(format_error)?,
+      None => entry.split_once(':').ok_or_else(format_error)?,
+    };
+    let (port, addrs) = rest.split_once(':').ok_or_else(format_error)?;
+    let port = port.parse::<u16>().map_err(|_| format!("Invalid resolve port {}", port))?;
+
+    let addrs = addrs.split(',').map(|addr| {
+      let addr = addr.trim().trim_start_matches('[').trim_end_matches(']');
+      addr.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, port)).map_err(|_| format!("Invalid resolve address {}", addr))
+    }).collect::<Result<Vec<_>, String>>()?;
+
+    Ok(ResolveOverride { host: host.to_string(), port, addrs })
+  }
+}
+
+// reqwest overrides a host on every port and connects to the port of the url, while the other modes only override the exact host:port
+// an override of the target host on another port would be honoured by some modes and not others, so it is rejected
+pub fn validate(config: &Config) -> Result<(), String> {
+  let Ok(target) = Target::parse_target(&config.url) else {
+    return Ok(());
+  };
+
+  match config.resolve.iter().find(|entry| entry.host.eq_ignore_ascii_case(&target.host) && entry.port != target.port) {
+    Some(entry) => Err(format!("--resolve {}:{} does not match the port {} of the target url, overrides apply to the port of the url", entry.host, entry.port, target.port)),
+    None => Ok(()),
+  }
+}
+
+// with --all-ips, every A/AAAA record of the target host becomes an override so virtual users are spread across them
+pub fn expand_target(config: &mut Config) -> Result<(), String> {
+  if !config.all_ips {
+    return Ok(());
+  }
+
+  let target = Target::parse_target(&config.url)?;
+  if overrides(config, &target.host, target.port).is_some() {
+    return Ok(());
+  }
+
+  let mut addrs: Vec<SocketAddr> = Vec::new();
+  for addr in target.address().to_socket_addrs().map_err(|e| format!("Unable to resolve {}: {}", target.host, e))? {
+    if !addrs.contains(&addr) {
+      addrs.push(addr);
+    }
+  }
+  config.resolve.push(ResolveOverride { host: target.host, port: target.port, addrs });
+
+  Ok(())
+}
+
+pub fn overrides<'a>(config: &'a Config, host: &str, port: u16) -> Option<&'a [SocketAddr]> {
+  config.resolve.iter()
+    .find(|entry| entry.host.eq_ignore_ascii_case(host) && entry.port == port)
+    .map(|entry| entry.addrs.as_slice())
+}
+
+// address used by the request's virtual user, None when host:port goes through the system resolver
+pub fn address(config: &Config, host: &str, port: u16, context: &RequestContext) -> Option<SocketAddr> {
+  overrides(config, host, port).map(|addrs| addrs[context.virtual_user % addrs.len()])
+}
+
+// addresses of the target host virtual users are spread across, empty when there is nothing to spread
+pub fn target_addrs(config: &Config) -> Vec<SocketAddr> {
+  let Ok(target) = Target::parse_target(&config.url) else {
+    return Vec::new();
+  };
+
+  match overrides(config, &target.host, target.port) {
+    Some(addrs) if addrs.len() > 1 => addrs.to_vec(),
+    _ => Vec::new(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_bracketed_ipv6_hosts() {
+    let entry = ResolveOverride::parse("[::1]:443:10.0.0.1").unwrap();
+    assert_eq!((entry.host.as_str(), entry.port), ("::1", 443));
+    assert_eq!(entry.addrs, vec!["10.0.0.1:443".parse::<SocketAddr>().unwrap()]);
+
+    let entry = ResolveOverride::parse("[2001:db8::1]:8443:[::1],127.0.0.1").unwrap();
+    assert_eq!((entry.host.as_str(), entry.port), ("2001:db8::1", 8443));
+    assert_eq!(entry.addrs, vec!["[::1]:8443".parse::<SocketAddr>().unwrap(), "127.0.0.1:8443".parse().unwrap()]);
+
+    let entry = ResolveOverride::parse("example.com:443:::1").unwrap();
+    assert_eq!((entry.host.as_str(), entry.port), ("example.com", 443));
+    assert_eq!(entry.addrs, vec!["[::1]:443".parse::<SocketAddr>().unwrap()]);
+
+    for entry in ["[::1:443:10.0.0.1", "[::1]443:10.0.0.1", "example.com:443", "::1:443:10.0.0.1"] {
+      assert!(ResolveOverride::parse(entry).is_err(), "{}", entry);
+    }
+  }
+
+  #[test]
+  fn validate_rejects_an_override_of_the_target_on_another_port() {
+    let config = crate::test_config(&["-u", "https://example.com/", "--resolve", "example.com:8443:127.0.0.1"]);
+    assert!(validate(&config).is_err());
+
+    let config = crate::test_config(&["-u", "https://example.com/", "--resolve", "example.com:443:127.0.0.1", "--resolve", "other.example.com:8443:127.0.0.1"]);
+    assert!(validate(&config).is_ok());
+
+    let config = crate::test_config(&["-u", "example.com:6379", "--mode", "tcp", "--resolve", "example.com:6379:127.0.0.1"]);
+    assert!(validate(&config).is_ok());
+  }
+}
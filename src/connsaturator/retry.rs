@@ -0,0 +1,294 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::RequestContext;
+use crate::connsaturator::ratelimit::RateLimitSignal;
+use crate::connsaturator::redirect::{self, Followed};
+
+use clap::ValueEnum;
+use rand::Rng;
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+
+use std::time::{Duration, Instant};
+
+// attempts a polite client makes after the first one before giving up, unless --retries says otherwise
+pub const POLITE_RETRIES: usize = 5;
+
+pub const BREAKDOWN: &str = "Attempt";
+
+// failures a request is retried on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RetryCondition {
+  Connect,
+  Timeout,
+  ServerError,
+  Status(u16),
+}
+
+impl RetryCondition {
+  // connect, timeout, 5xx or a status code
+  pub fn parse(condition: &str) -> Result<Self, String> {
+    match condition.trim().to_lowercase().as_str() {
+      "connect" => Ok(RetryCondition::Connect),
+      "timeout" => Ok(RetryCondition::Timeout),
+      "5xx" => Ok(RetryCondition::ServerError),
+      status => status.parse::<u16>().ok().filter(|status| (100..600).contains(status)).map(RetryCondition::Status)
+        .ok_or(format!("Invalid retry condition {}, expected connect, timeout, 5xx or a status code", condition)),
+    }
+  }
+
+  fn name(&self) -> String {
+    match self {
+      RetryCondition::Connect => "connect".to_string(),
+      RetryCondition::Timeout => "timeout".to_string(),
+      RetryCondition::ServerError => "5xx".to_string(),
+      RetryCondition::Status(status) => status.to_string(),
+    }
+  }
+}
+
+// wait between attempts, Retry-After always takes precedence
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq)]
+pub enum BackoffStrategy {
+  /// Retry right away
+  None,
+  /// Always wait --backoff-base
+  Fixed,
+  /// Double the wait on every retry, from --backoff-base up to --backoff-max
+  Exponential,
+  /// Random wait between zero and the exponential one (full jitter)
+  ExponentialJitter,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct RetryReport {
+  pub policy: String,
+  pub first_attempt_success: u64,
+  pub succeeded_after_retry: u64,
+  pub exhausted_retries: u64,
+  pub failed_without_retry: u64,
+  pub retried_requests: u64,
+  pub retries: u64,
+  pub avg_attempts_per_request: f64,
+  pub retry_after_waits: u64,
+  pub total_backoff_secs: f64,
+  pub avg_backoff_ms: f64,
+  pub avg_attempt_latency_ms: f64,
+  pub avg_end_to_end_latency_ms: f64,
+  #[serde(skip)]
+  requests: u64,
+  #[serde(skip)]
+  attempts: u64,
+  #[serde(skip)]
+  total_attempt_latency_ms: f64,
+  #[serde(skip)]
+  total_end_to_end_latency_ms: f64,
+}
+
+impl RetryReport {
+  // None when requests are never retried
+  pub fn new(config: &Config) -> Option<Self> {
+    let max_retries = max_retries(config);
+    if max_retries == 0 {
+      return None;
+    }
+
+    let conditions = retry_conditions(config).iter().map(RetryCondition::name).collect::<Vec<_>>().join(", ");
+    let backoff = match config.backoff {
+      BackoffStrategy::None => "no back-off".to_string(),
+      BackoffStrategy::Fixed => format!("fixed {} ms back-off", config.backoff_base),
+      BackoffStrategy::Exponential => format!("exponential back-off ({} ms base, {} ms max)", config.backoff_base, config.backoff_max),
+      BackoffStrategy::ExponentialJitter => format!("exponential back-off with jitter ({} ms base, {} ms max)", config.backoff_base, config.backoff_max),
+    };
+
+    let retry_after = match config.retry_after_max {
+      Some(max) => format!("Retry-After up to {} ms", max),
+      None => "Retry-After".to_string(),
+    };
+
+    Some(RetryReport {
+      policy: format!("{} retries on {}, {} or {}", max_retries, conditions, retry_after, backoff),
+      ..Default::default()
+    })
+  }
+
+  pub fn record(&mut self, attempts: &Attempts, success: bool) {
+    self.requests += 1;
+    self.attempts += attempts.history.len() as u64;
+    self.avg_attempts_per_request = self.attempts as f64 / self.requests as f64;
+
+    for attempt in &attempts.history {
+      self.total_attempt_latency_ms += attempt.latency.as_secs_f64() * 1000.0;
+    }
+    self.avg_attempt_latency_ms = self.total_attempt_latency_ms / self.attempts.max(1) as f64;
+    self.total_end_to_end_latency_ms += attempts.elapsed.as_secs_f64() * 1000.0;
+    self.avg_end_to_end_latency_ms = self.total_end_to_end_latency_ms / self.requests as f64;
+
+    match (success, attempts.retries, attempts.exhausted) {
+      (true, 0, _) => self.first_attempt_success += 1,
+      (true, _, _) => self.succeeded_after_retry += 1,
+      (false, _, true) => self.exhausted_retries += 1,
+      (false, _, false) => self.failed_without_retry += 1,
+    }
+
+    if attempts.retries > 0 {
+      self.retried_requests += 1;
+      self.retries += attempts.retries as u64;
+      self.retry_after_waits += attempts.retry_after_waits as u64;
+      self.total_backoff_secs += attempts.backoff.as_secs_f64();
+      self.avg_backoff_ms = self.total_backoff_secs * 1000.0 / self.retries as f64;
+    }
+  }
+
+  pub fn print(&self) {
+    println!("\nRetries:");
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {}", "Policy:", self.policy);
+    println!("{:<35} {}", "First attempt success:", self.first_attempt_success);
+    println!("{:<35} {}", "Succeeded after retry:", self.succeeded_after_retry);
+    println!("{:<35} {}", "Exhausted retries:", self.exhausted_retries);
+    println!("{:<35} {}", "Failed without retry:", self.failed_without_retry);
+    println!("{:<35} {} ({} retries)", "Retried requests:", self.retried_requests, self.retries);
+    println!("{:<35} {:.2}", "Attempts per request:", self.avg_attempts_per_request);
+    println!("{:<35} {}", "Waits taken from Retry-After:", self.retry_after_waits);
+    println!("{:<35} {:.2} s ({:.2} ms avg per retry)", "Time spent backing off:", self.total_backoff_secs, self.avg_backoff_ms);
+    println!("{:<35} {:.2} ms per attempt, {:.2} ms end to end", "Average latency:", self.avg_attempt_latency_ms, self.avg_end_to_end_latency_ms);
+  }
+}
+
+// a single try of a request
+#[derive(Clone, Debug)]
+pub struct Attempt {
+  pub latency: Duration,
+  pub status: String,
+  pub success: bool,
+  pub throttled: bool,
+  pub finished: Instant,
+  // only read for responses that were retried, the final one is read by the caller
+  pub rate_limit: RateLimitSignal,
+}
+
+// how a request got to its final response
+#[derive(Clone, Debug, Default)]
+pub struct Attempts {
+  pub history: Vec<Attempt>,
+  pub retries: usize,
+  pub retry_after_waits: usize,
+  pub backoff: Duration,
+  pub exhausted: bool,
+  // every attempt, back-off and discarded response included
+  pub elapsed: Duration,
+}
+
+// sends the request and tries again, after backing off, while it fails in a way the retry policy covers
+pub async fn send(client: &Client, config: &Config, context: &RequestContext) -> (reqwest::Result<Followed>, Attempts) {
+  let max_retries = max_retries(config);
+  let mut attempts = Attempts::default();
+  let start_time = Instant::now();
+
+  loop {
+    let attempt_start_time = Instant::now();
+    let result = redirect::send(client, config, context).await;
+    attempts.history.push(Attempt {
+      latency: attempt_start_time.elapsed(),
+      status: match &result {
+        Ok(followed) => followed.response.status().to_string(),
+        Err(e) => error_status(e).to_string(),
+      },
+      success: matches!(&result, Ok(followed) if followed.response.status().is_success()),
+      throttled: matches!(&result, Ok(followed) if followed.response.status() == StatusCode::TOO_MANY_REQUESTS),
+      finished: Instant::now(),
+      rate_limit: RateLimitSignal::default(),
+    });
+
+    if !should_retry(config, &result) {
+      attempts.elapsed = start_time.elapsed();
+      return (result, attempts);
+    }
+    if attempts.retries >= max_retries {
+      attempts.exhausted = max_retries > 0;
+      attempts.elapsed = start_time.elapsed();
+      return (result, attempts);
+    }
+
+    // the response is thrown away, so its body can be read for a retry_after_seconds field
+    let mut signal = RateLimitSignal::default();
+    if let Ok(followed) = result {
+      signal = RateLimitSignal::from_headers(followed.response.headers());
+      if let Ok(body) = followed.response.bytes().await {
+        signal.read_body(&body);
+      }
+    }
+
+    // a well-behaved client waits as long as it is asked to, unless --retry-after-max caps it
+    let delay = match signal.retry_after_secs {
+      Some(seconds) => {
+        attempts.retry_after_waits += 1;
+        let delay = Duration::try_from_secs_f64(seconds.max(0.0)).unwrap_or(Duration::MAX);
+        config.retry_after_max.map_or(delay, |max| delay.min(Duration::from_millis(max)))
+      },
+      None => backoff(config, attempts.retries),
+    };
+    if let Some(attempt) = attempts.history.last_mut() {
+      attempt.rate_limit = signal;
+    }
+    tokio::time::sleep(delay).await;
+
+    attempts.retries += 1;
+    attempts.backoff += delay;
+  }
+}
+
+pub fn max_retries(config: &Config) -> usize {
+  match (config.retries, config.polite) {
+    (Some(retries), _) => retries,
+    (None, true) => POLITE_RETRIES,
+    (None, false) => 0,
+  }
+}
+
+// --retry-on, connect errors, timeouts and 5xx by default, plus 429 and 503 for polite clients
+fn retry_conditions(config: &Config) -> Vec<RetryCondition> {
+  let mut conditions = config.retry_on.clone();
+  if conditions.is_empty() && config.retries.is_some() {
+    conditions = vec![RetryCondition::Connect, RetryCondition::Timeout, RetryCondition::ServerError];
+  }
+  if config.polite {
+    for status in [StatusCode::TOO_MANY_REQUESTS, StatusCode::SERVICE_UNAVAILABLE] {
+      if !conditions.contains(&RetryCondition::Status(status.as_u16())) {
+        conditions.push(RetryCondition::Status(status.as_u16()));
+      }
+    }
+  }
+
+  conditions
+}
+
+fn should_retry(config: &Config, result: &reqwest::Result<Followed>) -> bool {
+  retry_conditions(config).iter().any(|condition| match (condition, result) {
+    (RetryCondition::Connect, Err(e)) => e.is_connect(),
+    (RetryCondition::Timeout, Err(e)) => e.is_timeout(),
+    (RetryCondition::ServerError, Ok(followed)) => followed.response.status().is_server_error(),
+    (RetryCondition::Status(status), Ok(followed)) => followed.response.status().as_u16() == *status,
+    _ => false,
+  })
+}
+
+fn error_status(e: &reqwest::Error) -> &'static str {
+  if e.is_timeout() {
+    "Timeout"
+  } else if e.is_connect() {
+    "Connect Error"
+  } else {
+    "Network Error"
+  }
+}
+
+fn backoff(config: &Config, retry: usize) -> Duration {
+  let ceiling = config.backoff_base.saturating_mul(1 << retry.min(32)).min(config.backoff_max);
+  match config.backoff {
+    BackoffStrategy::None => Duration::ZERO,
+    BackoffStrategy::Fixed => Duration::from_millis(config.backoff_base),
+    BackoffStrategy::Exponential => Duration::from_millis(ceiling),
+    BackoffStrategy::ExponentialJitter => Duration::from_millis(rand::rng().random_range(0..=ceiling)),
+  }
+}
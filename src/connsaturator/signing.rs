@@ -0,0 +1,303 @@
+use crate::connsaturator::AuthMethods;
+use crate::connsaturator::Config;
+use crate::connsaturator::TestMode;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Request, Url};
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use sha2::{Digest, Sha256};
+
+use std::str::FromStr;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const DEFAULT_HMAC_TEMPLATE: &str = "{method}\\n{path}\\n{query}\\n{timestamp}\\n{body_sha256}";
+const PLACEHOLDERS: [&str; 7] = ["method", "path", "query", "host", "timestamp", "body", "body_sha256"];
+
+// checks the signing settings once, before any request is sent
+pub fn validate(config: &Config) -> Result<(), String> {
+  // the other modes build their requests without reqwest, they would go out unsigned
+  if let Some(AuthMethods::Hmac { .. } | AuthMethods::SigV4 { .. }) = &config.token
+    && !matches!(config.mode, TestMode::Http | TestMode::Stream | TestMode::Recovery) {
+    return Err(format!("HMAC and SigV4 signing are only supported in http, stream and recovery modes, not in {:?} mode", config.mode).to_lowercase());
+  }
+
+  match &config.token {
+    Some(AuthMethods::Hmac { .. }) => {
+      HeaderName::from_str(&config.sign_header).map_err(|e| format!("Invalid --sign-header {}: {}", config.sign_header, e))?;
+      render(&config.sign_template, |name| {
+        (PLACEHOLDERS.contains(&name) || name.starts_with("header:")).then(String::new)
+      }).map(|_| ())
+    },
+    Some(AuthMethods::SigV4 { .. }) if config.aws_region.is_none() => Err("SigV4 signing requires --aws-region".to_string()),
+    _ => Ok(()),
+  }
+}
+
+// adds a fresh signature to a fully built request, a no-op for non signing auth methods
+pub fn sign(request: &mut Request, config: &Config) {
+  sign_at(request, config, Utc::now());
+}
+
+fn sign_at(request: &mut Request, config: &Config, now: DateTime<Utc>) {
+  match &config.token {
+    Some(AuthMethods::Hmac { key_id, secret }) => sign_hmac(request, config, now, key_id.as_deref(), secret),
+    Some(AuthMethods::SigV4 { access_key, secret_key, session_token }) => {
+      let region = config.aws_region.as_deref().unwrap_or_default();
+      sign_sigv4(request, now, region, &config.aws_service, access_key, secret_key, session_token.as_deref());
+    },
+    _ => {},
+  }
+}
+
+fn sign_hmac(request: &mut Request, config: &Config, now: DateTime<Utc>, key_id: Option<&str>, secret: &str) {
+  let timestamp = now.timestamp().to_string();
+  let body = request.body().and_then(|body| body.as_bytes()).unwrap_or_default();
+
+  let canonical = render(&config.sign_template, |name| match name {
+    "method" => Some(request.method().as_str().to_string()),
+    "path" => Some(request.url().path().to_string()),
+    "query" => Some(request.url().query().unwrap_or_default().to_string()),
+    "host" => Some(host(request.url())),
+    "timestamp" => Some(timestamp.clone()),
+    "body" => Some(String::from_utf8_lossy(body).to_string()),
+    "body_sha256" => Some(hex::encode(Sha256::digest(body))),
+    _ => name.strip_prefix("header:").map(|header| {
+      request.headers().get(header).and_then(|value| value.to_str().ok()).unwrap_or_default().to_string()
+    }),
+  });
+  let Ok(canonical) = canonical else {
+    return;
+  };
+
+  let signature = hex::encode(hmac_sha256(secret.as_bytes(), canonical.as_bytes()));
+
+  let headers = request.headers_mut();
+  headers.insert("X-Timestamp", HeaderValue::from_str(&timestamp).unwrap());
+  if let Some(key_id) = key_id
+    && let Ok(value) = HeaderValue::from_str(key_id) {
+    headers.insert("X-Key-Id", value);
+  }
+  if let Ok(name) = HeaderName::from_str(&config.sign_header) {
+    headers.insert(name, HeaderValue::from_str(&signature).unwrap());
+  }
+}
+
+// AWS Signature Version 4, signing host, content-type and the x-amz-* headers
+fn sign_sigv4(request: &mut Request, now: DateTime<Utc>, region: &str, service: &str, access_key: &str, secret_key: &str, session_token: Option<&str>) {
+  let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+  let date = now.format("%Y%m%d").to_string();
+  let body = request.body().and_then(|body| body.as_bytes()).unwrap_or_default();
+  let payload_hash = hex::encode(Sha256::digest(body));
+
+  let headers = request.headers_mut();
+  headers.insert("x-amz-date", HeaderValue::from_str(&amz_date).unwrap());
+  if let Some(session_token) = session_token
+    && let Ok(value) = HeaderValue::from_str(session_token) {
+    headers.insert("x-amz-security-token", value);
+  }
+
+  let mut canonical_headers = vec![("host".to_string(), host(request.url()))];
+  for (name, value) in request.headers() {
+    if name == "content-type" || name.as_str().starts_with("x-amz-") {
+      canonical_headers.push((name.as_str().to_string(), value.to_str().unwrap_or_default().trim().to_string()));
+    }
+  }
+  canonical_headers.sort();
+
+  let signed_headers = canonical_headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+  let canonical_request = format!(
+    "{}\n{}\n{}\n{}\n{}\n{}",
+    request.method().as_str(),
+    canonical_uri(request.url()),
+    canonical_query(request.url()),
+    canonical_headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect::<String>(),
+    signed_headers,
+    payload_hash,
+  );
+
+  let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+  let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, hex::encode(Sha256::digest(canonical_request.as_bytes())));
+
+  let mut key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+  for part in [region, service, "aws4_request"] {
+    key = hmac_sha256(&key, part.as_bytes());
+  }
+  let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+  let authorization = format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", access_key, scope, signed_headers, signature);
+  if let Ok(value) = HeaderValue::from_str(&authorization) {
+    request.headers_mut().insert(AUTHORIZATION, value);
+  }
+}
+
+// expands {placeholders} and \n / \t escapes of a canonical string template
+fn render(template: &str, resolve: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+  let template = template.replace("\\n", "\n").replace("\\t", "\t");
+  let mut rendered = String::new();
+  let mut rest = template.as_str();
+
+  while let Some(start) = rest.find('{') {
+    let end = rest[start..].find('}').ok_or(format!("Unclosed placeholder in --sign-template: {}", &rest[start..]))? + start;
+    let name = &rest[start + 1..end];
+    rendered.push_str(&rest[..start]);
+    rendered.push_str(&resolve(name).ok_or(format!("Unknown placeholder {{{}}} in --sign-template", name))?);
+    rest = &rest[end + 1..];
+  }
+  rendered.push_str(rest);
+
+  Ok(rendered)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+  let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+  mac.update(data);
+  mac.finalize().into_bytes().to_vec()
+}
+
+// value of the Host header reqwest sends, the port is only included when it is not the scheme default
+fn host(url: &Url) -> String {
+  let host = url.host_str().unwrap_or_default();
+  match url.port() {
+    Some(port) => format!("{}:{}", host, port),
+    None => host.to_string(),
+  }
+}
+
+// Url keeps the path percent-encoded, each segment is decoded first so it ends up encoded exactly once
+fn canonical_uri(url: &Url) -> String {
+  let path = url.path();
+  if path.is_empty() {
+    return "/".to_string();
+  }
+  path.split('/').map(|segment| uri_encode(&percent_decode(segment))).collect::<Vec<_>>().join("/")
+}
+
+fn canonical_query(url: &Url) -> String {
+  let mut pairs = url.query_pairs()
+    .map(|(key, value)| (uri_encode(key.as_bytes()), uri_encode(value.as_bytes())))
+    .collect::<Vec<_>>();
+  pairs.sort();
+  pairs.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join("&")
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+  let bytes = value.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut index = 0;
+  while index < bytes.len() {
+    let escaped = bytes.get(index + 1..index + 3)
+      .filter(|_| bytes[index] == b'%')
+      .and_then(|hex| std::str::from_utf8(hex).ok())
+      .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    match escaped {
+      Some(byte) => {
+        decoded.push(byte);
+        index += 3;
+      },
+      None => {
+        decoded.push(bytes[index]);
+        index += 1;
+      },
+    }
+  }
+  decoded
+}
+
+fn uri_encode(value: &[u8]) -> String {
+  value.iter().map(|&byte| match byte {
+    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+    _ => format!("%{:02X}", byte),
+  }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::TimeZone;
+  use reqwest::Method;
+
+  const SECRET_KEY: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+
+  // 20150830T123600Z, the date of the AWS SigV4 test suite
+  fn now() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap()
+  }
+
+  fn sigv4_authorization(url: &str) -> String {
+    let token = format!("SigV4 AKIDEXAMPLE:{}", SECRET_KEY);
+    let config = crate::test_config(&["-u", url, "--token", &token, "--aws-region", "us-east-1", "--aws-service", "service"]);
+    let mut request = Request::new(Method::GET, Url::parse(url).unwrap());
+    sign_at(&mut request, &config, now());
+    assert_eq!(request.headers()["x-amz-date"], "20150830T123600Z");
+    request.headers()[AUTHORIZATION].to_str().unwrap().to_string()
+  }
+
+  fn signature(authorization: &str) -> &str {
+    authorization.rsplit_once("Signature=").unwrap().1
+  }
+
+  #[test]
+  fn sigv4_get_vanilla() {
+    assert_eq!(
+      sigv4_authorization("https://example.amazonaws.com/"),
+      "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31",
+    );
+  }
+
+  #[test]
+  fn sigv4_get_vanilla_query_order_key_case() {
+    let authorization = sigv4_authorization("https://example.amazonaws.com/?Param2=value2&Param1=value1");
+    assert_eq!(signature(&authorization), "b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500");
+  }
+
+  #[test]
+  fn sigv4_sorts_repeated_query_keys_by_value() {
+    let authorization = sigv4_authorization("https://example.amazonaws.com/?Param1=value2&Param1=value1");
+    assert_eq!(signature(&authorization), "5772eed61e12b33fae39ee5e7012498b51d56abc0abb7c60486157bd471c4694");
+  }
+
+  #[test]
+  fn sigv4_get_utf8() {
+    let authorization = sigv4_authorization("https://example.amazonaws.com/ሴ");
+    assert_eq!(signature(&authorization), "8318018e0b0f223aa2bbf98705b62bb787dc9c0e678f255a891fd03141be5d85");
+  }
+
+  #[test]
+  fn sigv4_get_space() {
+    let authorization = sigv4_authorization("https://example.amazonaws.com/example space/");
+    assert_eq!(signature(&authorization), "652487583200325589f1fba4c7e578f72c47cb61beeca81406b39ddec1366741");
+  }
+
+  #[test]
+  fn canonical_uri_encodes_each_segment_once() {
+    assert_eq!(canonical_uri(&Url::parse("https://example.com/example%20space/a%2Fb").unwrap()), "/example%20space/a%2Fb");
+    assert_eq!(canonical_uri(&Url::parse("https://example.com/%E1%88%B4").unwrap()), "/%E1%88%B4");
+  }
+
+  #[test]
+  fn hmac_default_template() {
+    let url = "https://api.example.com/v1/items?b=2&a=1";
+    let config = crate::test_config(&["-u", url, "--token", "HMAC key1:secret"]);
+    let mut request = Request::new(Method::POST, Url::parse(url).unwrap());
+    *request.body_mut() = Some(r#"{"name":"widget"}"#.into());
+    sign_at(&mut request, &config, now());
+
+    // HMAC-SHA256 of "POST\n/v1/items\nb=2&a=1\n1440938160\n" plus the hex SHA-256 of the body
+    assert_eq!(request.headers()["X-Timestamp"], "1440938160");
+    assert_eq!(request.headers()["X-Key-Id"], "key1");
+    assert_eq!(request.headers()["X-Signature"], "2862ed82c454c56c0c107956423a675b8c039ce1a0afb54b42b4bfe95f269e97");
+  }
+
+  #[test]
+  fn signing_is_rejected_outside_reqwest_modes() {
+    for mode in ["ws", "grpc", "slow", "tcp", "handshake"] {
+      let config = crate::test_config(&["-u", "https://example.com/", "--token", "HMAC secret", "--mode", mode]);
+      assert!(validate(&config).is_err(), "{} mode", mode);
+    }
+    let config = crate::test_config(&["-u", "https://example.com/", "--token", "HMAC secret", "--mode", "stream"]);
+    assert!(validate(&config).is_ok());
+  }
+}
@@ -0,0 +1,371 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::HttpMethods;
+use crate::connsaturator::LoadResult;
+use crate::connsaturator::RequestContext;
+use crate::connsaturator::SlowMode;
+use crate::connsaturator::net::{self, ConnectOptions, Target};
+use crate::connsaturator::requestbuilder;
+use crate::connsaturator::resolve;
+use crate::connsaturator::saturator::calculate_percentiles;
+use crate::connsaturator::tcp::connect_error;
+use crate::connsaturator::tls;
+
+use indicatif::ProgressBar;
+use reqwest::Url;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Semaphore;
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// small receive window so the server cannot park the whole response in our kernel buffers
+const SLOW_READ_BUFFER_SIZE: u32 = 4096;
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct SlowClientReport {
+  pub slow_mode: String,
+  pub interval_ms: u64,
+  pub chunk_bytes: usize,
+  pub connections_opened: u64,
+  pub closed_by_server: u64,
+  pub completed: u64,
+  pub survived: u64,
+  pub min_time_to_close_ms: f64,
+  pub avg_time_to_close_ms: f64,
+  pub p50_time_to_close_ms: f64,
+  pub max_time_to_close_ms: f64,
+}
+
+impl SlowClientReport {
+  pub fn print(&self) {
+    println!("\nSlow Client ({}):", self.slow_mode);
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {} bytes every {} ms", "Pace:", self.chunk_bytes, self.interval_ms);
+    println!("{:<35} {}", "Connections opened:", self.connections_opened);
+    println!("{:<35} {}", "Closed by server (timeout hit):", self.closed_by_server);
+    println!("{:<35} {}", "Completed despite slowness:", self.completed);
+    println!("{:<35} {}", "Still open at end of hold:", self.survived);
+    if self.closed_by_server > 0 {
+      println!("{:<35} {:.2} ms", "Min time to close:", self.min_time_to_close_ms);
+      println!("{:<35} {:.2} ms", "Average time to close:", self.avg_time_to_close_ms);
+      println!("{:<35} {:.2} ms", "p50 time to close:", self.p50_time_to_close_ms);
+      println!("{:<35} {:.2} ms", "Max time to close:", self.max_time_to_close_ms);
+    }
+  }
+}
+
+enum SlowEnd {
+  Closed(String),
+  Completed(String),
+  Survived,
+}
+
+struct SlowOutcome {
+  opened: bool,
+  status: String,
+  end: Option<SlowEnd>,
+  elapsed: Duration,
+  bytes: u64,
+}
+
+// keeps `requests` deliberately slow connections going, `concurrency` at a time, and records when the server gives up on each one
+pub async fn execute_connections(config: &Config, progress_bar: &ProgressBar) -> Result<LoadResult, String> {
+  let target = Target::parse_target(&config.url)?;
+  let url = Url::parse(&config.url).map_err(|e| format!("Invalid URL {}: {}", config.url, e))?;
+  let tls_config = if target.tls {
+    Some(tls::client_config(config)?)
+  } else {
+    None
+  };
+
+  let body = match config.slow_mode {
+    SlowMode::Body => config.body.clone().unwrap_or_else(|| "x".repeat(1024)),
+    _ => config.body.clone().unwrap_or_default(),
+  };
+
+  let mut latencies = Vec::new();
+  let mut status_codes = HashMap::new();
+  let mut success_counter = 0;
+  let mut error_counter = 0;
+  let total_bytes = AtomicU64::new(0);
+  let mut report = SlowClientReport {
+    slow_mode: format!("{:?}", config.slow_mode).to_lowercase(),
+    interval_ms: config.slow_interval,
+    chunk_bytes: config.slow_chunk,
+    ..Default::default()
+  };
+
+  let semaphore = Arc::new(Semaphore::new(config.concurrency));
+  let target = Arc::new(target);
+  let body = Arc::new(body.into_bytes());
+  let config = Arc::new(config.clone());
+
+  let start_time = Instant::now();
+
+  let mut handles = Vec::new();
+
+  for iteration in 0..config.requests {
+    let clonned_target = Arc::clone(&target);
+    let clonned_tls_config = tls_config.clone();
+    let context = RequestContext::new(iteration as u64, &config);
+    let head = request_head(&url, &target, &config, body.len(), &context).into_bytes();
+    let clonned_body = Arc::clone(&body);
+    let clonned_config = Arc::clone(&config);
+    let clonned_progress_bar = progress_bar.clone();
+
+    let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
+
+    let handle = tokio::spawn(async move {
+      let _permit = permit;
+      let outcome = slow_connection(&clonned_target, clonned_tls_config, &head, &clonned_body, &clonned_config, &context).await;
+      clonned_progress_bar.inc(1);
+      outcome
+    });
+    handles.push(handle);
+  }
+
+  for handle in handles {
+    match handle.await {
+      Ok(outcome) => {
+        if outcome.opened {
+          report.connections_opened += 1;
+        }
+
+        // the server enforcing its timeouts is the expected result here
+        match outcome.end {
+          Some(SlowEnd::Closed(_)) => {
+            success_counter += 1;
+            report.closed_by_server += 1;
+            latencies.push(outcome.elapsed);
+          },
+          Some(SlowEnd::Completed(_)) => {
+            error_counter += 1;
+            report.completed += 1;
+          },
+          Some(SlowEnd::Survived) => {
+            error_counter += 1;
+            report.survived += 1;
+          },
+          None => error_counter += 1,
+        }
+
+        total_bytes.fetch_add(outcome.bytes, Ordering::Relaxed);
+        *status_codes.entry(outcome.status).or_insert(0) += 1;
+      },
+      Err(_) => {
+        error_counter += 1;
+        *status_codes.entry("Panic Error".to_string()).or_insert(0) += 1;
+      }
+    }
+  }
+
+  let duration = start_time.elapsed();
+  latencies.sort();
+
+  if !latencies.is_empty() {
+    let percentiles = calculate_percentiles(&latencies);
+    let total: Duration = latencies.iter().sum();
+    report.min_time_to_close_ms = latencies[0].as_secs_f64() * 1000.0;
+    report.avg_time_to_close_ms = total.as_secs_f64() * 1000.0 / latencies.len() as f64;
+    report.p50_time_to_close_ms = percentiles["p50"];
+    report.max_time_to_close_ms = latencies[latencies.len() - 1].as_secs_f64() * 1000.0;
+  }
+
+  Ok(LoadResult {
+    latencies,
+    status_codes,
+    success_counter,
+    error_counter,
+    duration,
+    total_bytes,
+    slow_client: Some(report),
+    ..Default::default()
+  })
+}
+
+async fn slow_connection(target: &Target, tls_config: Option<Arc<rustls::ClientConfig>>, head: &[u8], body: &[u8], config: &Config, context: &RequestContext) -> SlowOutcome {
+  let options = ConnectOptions {
+    recv_buffer_size: if config.slow_mode == SlowMode::Read { Some(SLOW_READ_BUFFER_SIZE) } else { None },
+    address: resolve::address(config, &target.host, target.port, context),
+    source: net::source_address(config, context),
+  };
+
+  let connection = tokio::time::timeout(Duration::from_secs(config.timeout), net::connect(target, tls_config, &options)).await;
+  let connection = match connection {
+    Ok(Ok(connection)) => connection,
+    Ok(Err(e)) => return SlowOutcome { opened: false, status: connect_error(&e).to_string(), end: None, elapsed: Duration::from_secs(0), bytes: 0 },
+    Err(_) => return SlowOutcome { opened: false, status: "Connect Timeout".to_string(), end: None, elapsed: Duration::from_secs(0), bytes: 0 },
+  };
+
+  let (mut reader, mut writer) = tokio::io::split(connection);
+  let opened_at = Instant::now();
+  let interval = Duration::from_millis(config.slow_interval.max(1));
+  let chunk = config.slow_chunk.max(1);
+
+  // what gets trickled: the whole head for slow headers, only the body for slow body, nothing for slow read
+  let (fast, slow): (Vec<u8>, &[u8]) = match config.slow_mode {
+    SlowMode::Headers => (Vec::new(), head),
+    SlowMode::Body => (head.to_vec(), body),
+    SlowMode::Read => ([head, body].concat(), &[]),
+  };
+
+  let mut response: Vec<u8> = Vec::new();
+  let mut bytes = 0u64;
+
+  let hold = tokio::time::sleep(Duration::from_secs(config.hold));
+  tokio::pin!(hold);
+
+  let end = 'connection: {
+    if !fast.is_empty() && let Err(e) = writer.write_all(&fast).await {
+      break 'connection closed_end(&e, &response);
+    }
+
+    let mut sent = 0;
+    let mut ticker = tokio::time::interval(interval);
+    let mut buffer = vec![0u8; if config.slow_mode == SlowMode::Read { chunk } else { 8192 }];
+
+    loop {
+      let sending = sent < slow.len();
+      tokio::select! {
+        _ = &mut hold => break SlowEnd::Survived,
+        _ = ticker.tick(), if sending => {
+          let end = (sent + chunk).min(slow.len());
+          if let Err(e) = writer.write_all(&slow[sent..end]).await {
+            break closed_end(&e, &response);
+          }
+          sent = end;
+          if sent == slow.len() && config.slow_mode == SlowMode::Headers && let Err(e) = writer.write_all(body).await {
+            break closed_end(&e, &response);
+          }
+        },
+        read = slow_read(&mut reader, &mut buffer, config.slow_mode == SlowMode::Read, interval) => {
+          match read {
+            Ok(0) => break SlowEnd::Closed(close_label(&response, "Closed By Server")),
+            Ok(read) => {
+              bytes += read as u64;
+              if response.len() < 8192 {
+                response.extend_from_slice(&buffer[..read]);
+              }
+              if response_complete(&response, bytes) {
+                break if sending { SlowEnd::Closed(close_label(&response, "Closed By Server")) } else { SlowEnd::Completed(close_label(&response, "Completed")) };
+              }
+            },
+            Err(e) => break closed_end(&e, &response),
+          }
+        },
+      }
+    }
+  };
+
+  let status = match &end {
+    SlowEnd::Closed(label) | SlowEnd::Completed(label) => label.clone(),
+    SlowEnd::Survived => "Survived".to_string(),
+  };
+
+  SlowOutcome { opened: true, status, end: Some(end), elapsed: opened_at.elapsed(), bytes }
+}
+
+// slow read mode waits between every small read, the other modes read as soon as the server answers
+async fn slow_read<R: AsyncReadExt + Unpin>(reader: &mut R, buffer: &mut [u8], slow: bool, interval: Duration) -> io::Result<usize> {
+  if slow {
+    tokio::time::sleep(interval).await;
+  }
+  reader.read(buffer).await
+}
+
+fn closed_end(error: &io::Error, response: &[u8]) -> SlowEnd {
+  match error.kind() {
+    io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe => SlowEnd::Closed(close_label(response, "Reset By Server")),
+    _ => SlowEnd::Closed(close_label(response, "Closed By Server")),
+  }
+}
+
+// the status line, when the server answered before dropping us (e.g. 408 Request Timeout)
+fn close_label(response: &[u8], default: &str) -> String {
+  let text = String::from_utf8_lossy(response);
+  match text.lines().next().and_then(|line| line.strip_prefix("HTTP/")).and_then(|line| line.split_once(' ')) {
+    Some((_, status)) => format!("{} ({})", default, status.trim()),
+    None => default.to_string(),
+  }
+}
+
+fn response_complete(response: &[u8], bytes: u64) -> bool {
+  let text = String::from_utf8_lossy(response);
+  let Some(head_end) = text.find("\r\n\r\n") else {
+    return false;
+  };
+
+  let content_length = text[..head_end].lines()
+    .filter_map(|line| line.split_once(':'))
+    .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+    .and_then(|(_, value)| value.trim().parse::<u64>().ok());
+
+  match content_length {
+    Some(length) => bytes >= (head_end + 4) as u64 + length,
+    None => false,
+  }
+}
+
+fn request_head(url: &Url, target: &Target, config: &Config, body_length: usize, context: &RequestContext) -> String {
+  let method = match config.method {
+    HttpMethods::Get => "GET",
+    HttpMethods::Post => "POST",
+    HttpMethods::Put => "PUT",
+    HttpMethods::Delete => "DELETE",
+  };
+
+  let url = Url::parse(&requestbuilder::create_url(url.as_str(), config, context)).unwrap_or_else(|_| url.clone());
+  let path = match url.query() {
+    Some(query) => format!("{}?{}", url.path(), query),
+    None => url.path().to_string(),
+  };
+
+  // brackets around ipv6 literals, and the port only when it is not the default of the scheme
+  let host = match url.port() {
+    Some(port) => format!("{}:{}", url.host_str().unwrap_or(&target.host), port),
+    None => url.host_str().unwrap_or(&target.host).to_string(),
+  };
+
+  let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, host);
+  for (name, value) in requestbuilder::create_headers(config, context).iter() {
+    if let Ok(value) = value.to_str() {
+      head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+  }
+
+  if body_length > 0 {
+    head.push_str(&format!("Content-Type: {}\r\nContent-Length: {}\r\n", config.content_type, body_length));
+  }
+  head.push_str("Connection: keep-alive\r\n\r\n");
+
+  head
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn host_line(url: &str) -> String {
+    let config = crate::test_config(&["-u", url, "--mode", "slow"]);
+    let url = Url::parse(url).unwrap();
+    let target = Target::parse_target(url.as_str()).unwrap();
+    let head = request_head(&url, &target, &config, 0, &RequestContext::new(0, &config));
+    head.lines().find(|line| line.starts_with("Host:")).unwrap().to_string()
+  }
+
+  #[test]
+  fn host_header_keeps_ipv6_brackets() {
+    assert_eq!(host_line("http://[::1]:8080/"), "Host: [::1]:8080");
+    assert_eq!(host_line("http://[::1]/"), "Host: [::1]");
+  }
+
+  #[test]
+  fn host_header_has_port_only_when_not_default() {
+    assert_eq!(host_line("http://example.com:8080/"), "Host: example.com:8080");
+    assert_eq!(host_line("https://example.com:443/"), "Host: example.com");
+  }
+}
@@ -0,0 +1,250 @@
+use crate::connsaturator::ClientPool;
+use crate::connsaturator::Config;
+use crate::connsaturator::LoadResult;
+use crate::connsaturator::RequestContext;
+use crate::connsaturator::requestbuilder;
+use crate::connsaturator::saturator::calculate_percentiles;
+
+use indicatif::ProgressBar;
+use reqwest::Client;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use serde::Serialize;
+use tokio::sync::Semaphore;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct StreamReport {
+  pub streams_opened: u64,
+  pub dropped_streams: u64,
+  pub closed_streams: u64,
+  pub streams_without_events: u64,
+  pub total_events: u64,
+  pub avg_time_to_first_event_ms: f64,
+  pub p95_time_to_first_event_ms: f64,
+  pub avg_events_per_sec: f64,
+  pub min_events_per_sec: f64,
+  pub max_events_per_sec: f64,
+}
+
+impl StreamReport {
+  pub fn print(&self) {
+    println!("\nStreaming:");
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {}", "Streams opened:", self.streams_opened);
+    println!("{:<35} {}", "Dropped streams:", self.dropped_streams);
+    println!("{:<35} {}", "Streams ended by the server:", self.closed_streams);
+    println!("{:<35} {}", "Streams without events:", self.streams_without_events);
+    println!("{:<35} {}", "Events received:", self.total_events);
+    println!("{:<35} {:.2} ms", "Average time to first event:", self.avg_time_to_first_event_ms);
+    println!("{:<35} {:.2} ms", "p95 time to first event:", self.p95_time_to_first_event_ms);
+    println!("{:<35} {:.2} events/s", "Average events per connection:", self.avg_events_per_sec);
+    println!("{:<35} {:.2} events/s", "Min events per connection:", self.min_events_per_sec);
+    println!("{:<35} {:.2} events/s", "Max events per connection:", self.max_events_per_sec);
+  }
+}
+
+struct StreamOutcome {
+  opened: bool,
+  first_event: Option<Duration>,
+  status: String,
+  success: bool,
+  dropped: bool,
+  // the server ended the response before the hold was over, as a long-poll does once it answers
+  closed: bool,
+  events: u64,
+  held: Duration,
+  bytes: u64,
+}
+
+impl StreamOutcome {
+  fn failed(status: String) -> Self {
+    StreamOutcome { opened: false, first_event: None, status, success: false, dropped: false, closed: false, events: 0, held: Duration::from_secs(0), bytes: 0 }
+  }
+}
+
+// opens `requests` streaming responses, at most `concurrency` at the same time, and keeps reading each one for `hold` seconds
+pub async fn execute_streams(clients: &ClientPool, config: &Config, progress_bar: &ProgressBar) -> LoadResult {
+  let mut latencies = Vec::new();
+  let mut status_codes = HashMap::new();
+  let mut success_counter = 0;
+  let mut error_counter = 0;
+  let total_bytes = AtomicU64::new(0);
+  let mut events_per_sec: Vec<f64> = Vec::new();
+  let mut report = StreamReport::default();
+
+  let semaphore = Arc::new(Semaphore::new(config.concurrency));
+  let clients = Arc::new(clients.clone());
+  let config = Arc::new(config.clone());
+
+  let start_time = Instant::now();
+
+  let mut handles = Vec::new();
+
+  for iteration in 0..config.requests {
+    let clonned_clients = Arc::clone(&clients);
+    let clonned_config = Arc::clone(&config);
+    let context = RequestContext::new(iteration as u64, &config);
+    let clonned_progress_bar = progress_bar.clone();
+
+    let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
+
+    let handle = tokio::spawn(async move {
+      let _permit = permit;
+      let outcome = open_stream(clonned_clients.client(&context), &clonned_config, &context).await;
+      clonned_progress_bar.inc(1);
+      outcome
+    });
+    handles.push(handle);
+  }
+
+  for handle in handles {
+    match handle.await {
+      Ok(outcome) => {
+        if outcome.opened {
+          report.streams_opened += 1;
+          if outcome.held.as_secs_f64() > 0.0 {
+            events_per_sec.push(outcome.events as f64 / outcome.held.as_secs_f64());
+          }
+        }
+
+        match outcome.first_event {
+          Some(first_event) => latencies.push(first_event),
+          None if outcome.opened => report.streams_without_events += 1,
+          None => {},
+        }
+
+        if outcome.success {
+          success_counter += 1;
+        } else {
+          error_counter += 1;
+        }
+
+        if outcome.dropped {
+          report.dropped_streams += 1;
+        }
+        if outcome.closed {
+          report.closed_streams += 1;
+        }
+
+        report.total_events += outcome.events;
+        total_bytes.fetch_add(outcome.bytes, Ordering::Relaxed);
+
+        *status_codes.entry(outcome.status).or_insert(0) += 1;
+      },
+      Err(_) => {
+        error_counter += 1;
+        *status_codes.entry("Panic Error".to_string()).or_insert(0) += 1;
+      }
+    }
+  }
+
+  let duration = start_time.elapsed();
+  latencies.sort();
+
+  if !latencies.is_empty() {
+    let percentiles = calculate_percentiles(&latencies);
+    let total: Duration = latencies.iter().sum();
+    report.avg_time_to_first_event_ms = total.as_secs_f64() * 1000.0 / latencies.len() as f64;
+    report.p95_time_to_first_event_ms = percentiles["p95"];
+  }
+
+  if !events_per_sec.is_empty() {
+    report.avg_events_per_sec = events_per_sec.iter().sum::<f64>() / events_per_sec.len() as f64;
+    report.min_events_per_sec = events_per_sec.iter().cloned().fold(f64::INFINITY, f64::min);
+    report.max_events_per_sec = events_per_sec.iter().cloned().fold(0.0, f64::max);
+  }
+
+  LoadResult {
+    latencies,
+    status_codes,
+    success_counter,
+    error_counter,
+    duration,
+    total_bytes,
+    stream: Some(report),
+    ..Default::default()
+  }
+}
+
+async fn open_stream(client: &Client, config: &Config, context: &RequestContext) -> StreamOutcome {
+  let hold = Duration::from_secs(config.hold);
+  let builder = requestbuilder::create_builder(client, config, context)
+    .header(ACCEPT, "text/event-stream")
+    .timeout(hold + Duration::from_secs(config.timeout));
+
+  let request_start_time = Instant::now();
+  let mut response = match tokio::time::timeout(Duration::from_secs(config.timeout), builder.send()).await {
+    Ok(Ok(response)) => response,
+    Ok(Err(_)) => return StreamOutcome::failed("Network Error".to_string()),
+    Err(_) => return StreamOutcome::failed("Connect Timeout".to_string()),
+  };
+
+  let mut outcome = StreamOutcome::failed(response.status().to_string());
+  if !response.status().is_success() {
+    return outcome;
+  }
+  outcome.opened = true;
+
+  // SSE events end with a blank line; anything else is treated as one event per chunk
+  let server_sent_events = response.headers().get(CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .is_some_and(|value| value.contains("text/event-stream"));
+  let mut last_byte = 0u8;
+
+  let opened_at = Instant::now();
+  let deadline = tokio::time::sleep(hold);
+  tokio::pin!(deadline);
+
+  loop {
+    tokio::select! {
+      _ = &mut deadline => break,
+      chunk = response.chunk() => {
+        match chunk {
+          Ok(Some(chunk)) => {
+            outcome.bytes += chunk.len() as u64;
+            let events = if server_sent_events {
+              let mut events = 0;
+              for byte in chunk.iter().filter(|byte| **byte != b'\r') {
+                if *byte == b'\n' && last_byte == b'\n' {
+                  events += 1;
+                  last_byte = 0;
+                } else {
+                  last_byte = *byte;
+                }
+              }
+              events
+            } else {
+              1
+            };
+
+            if events > 0 && outcome.first_event.is_none() {
+              outcome.first_event = Some(request_start_time.elapsed());
+            }
+            outcome.events += events;
+          },
+          Ok(None) => {
+            outcome.closed = true;
+            break;
+          },
+          Err(_) => {
+            outcome.dropped = true;
+            break;
+          },
+        }
+      },
+    }
+  }
+
+  outcome.held = opened_at.elapsed();
+  if outcome.dropped {
+    outcome.status = "Stream Dropped".to_string();
+  } else {
+    outcome.success = true;
+  }
+
+  outcome
+}
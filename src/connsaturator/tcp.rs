@@ -0,0 +1,249 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::LoadResult;
+use crate::connsaturator::RequestContext;
+use crate::connsaturator::net::{self, ConnectOptions, Target};
+use crate::connsaturator::resolve;
+use crate::connsaturator::saturator::calculate_percentiles;
+use crate::connsaturator::tls;
+
+use indicatif::ProgressBar;
+use serde::Serialize;
+use tokio::io::AsyncReadExt;
+use tokio::sync::Semaphore;
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct TcpReport {
+  pub target: String,
+  pub tls: bool,
+  pub peak_open_connections: u64,
+  pub open_connections_at_first_failure: Option<u64>,
+  pub held_until_end: u64,
+  pub closed_by_server: u64,
+  pub reset_by_server: u64,
+  pub min_idle_lifetime_ms: f64,
+  pub avg_idle_lifetime_ms: f64,
+  pub p50_idle_lifetime_ms: f64,
+}
+
+impl TcpReport {
+  pub fn print(&self) {
+    println!("\nTCP Connections:");
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {}{}", "Target:", self.target, if self.tls { " (TLS)" } else { "" });
+    println!("{:<35} {}", "Peak open connections:", self.peak_open_connections);
+    match self.open_connections_at_first_failure {
+      Some(open) => println!("{:<35} {}", "Open connections at first failure:", open),
+      None => println!("{:<35} none", "Open connections at first failure:"),
+    }
+    println!("{:<35} {}", "Held until end of test:", self.held_until_end);
+    println!("{:<35} {}", "Closed by server while idle:", self.closed_by_server);
+    println!("{:<35} {}", "Reset by server while idle:", self.reset_by_server);
+    if self.closed_by_server + self.reset_by_server > 0 {
+      println!("{:<35} {:.2} ms", "Min idle lifetime:", self.min_idle_lifetime_ms);
+      println!("{:<35} {:.2} ms", "Average idle lifetime:", self.avg_idle_lifetime_ms);
+      println!("{:<35} {:.2} ms", "p50 idle lifetime:", self.p50_idle_lifetime_ms);
+    }
+  }
+}
+
+enum ConnectionEnd {
+  Held,
+  Closed,
+  Reset,
+}
+
+struct ConnectionOutcome {
+  connect_time: Option<Duration>,
+  status: String,
+  end: Option<ConnectionEnd>,
+  lifetime: Duration,
+  bytes: u64,
+}
+
+// opens `requests` connections, at most `concurrency` connect attempts at a time, and keeps every established one idle for `hold` seconds
+pub async fn execute_connections(config: &Config, progress_bar: &ProgressBar) -> Result<LoadResult, String> {
+  let target = Target::parse_target(&config.url)?;
+  let tls_config = if target.tls {
+    Some(tls::client_config(config)?)
+  } else {
+    None
+  };
+
+  let mut latencies = Vec::new();
+  let mut status_codes = HashMap::new();
+  let mut success_counter = 0;
+  let mut error_counter = 0;
+  let total_bytes = AtomicU64::new(0);
+  let mut lifetimes = Vec::new();
+  let mut report = TcpReport { target: target.address(), tls: target.tls, ..Default::default() };
+
+  let semaphore = Arc::new(Semaphore::new(config.concurrency));
+  let open_connections = Arc::new(AtomicU64::new(0));
+  let peak_open_connections = Arc::new(AtomicU64::new(0));
+  let first_failure = Arc::new(AtomicU64::new(u64::MAX));
+  let target = Arc::new(target);
+  let config = Arc::new(config.clone());
+
+  let start_time = Instant::now();
+
+  let mut handles = Vec::new();
+
+  for iteration in 0..config.requests {
+    let clonned_target = Arc::clone(&target);
+    let context = RequestContext::new(iteration as u64, &config);
+    let options = ConnectOptions {
+      address: resolve::address(&config, &target.host, target.port, &context),
+      source: net::source_address(&config, &context),
+      ..Default::default()
+    };
+    let clonned_tls_config = tls_config.clone();
+    let clonned_config = Arc::clone(&config);
+    let clonned_open_connections = Arc::clone(&open_connections);
+    let clonned_peak_open_connections = Arc::clone(&peak_open_connections);
+    let clonned_first_failure = Arc::clone(&first_failure);
+    let clonned_progress_bar = progress_bar.clone();
+
+    let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
+
+    let handle = tokio::spawn(async move {
+      let connect_start_time = Instant::now();
+      let connection = tokio::time::timeout(
+        Duration::from_secs(clonned_config.timeout),
+        net::connect(&clonned_target, clonned_tls_config, &options),
+      ).await;
+      drop(permit);
+
+      let mut connection = match connection {
+        Ok(Ok(connection)) => connection,
+        Ok(Err(e)) => {
+          let open = clonned_open_connections.load(Ordering::Relaxed);
+          let _ = clonned_first_failure.compare_exchange(u64::MAX, open, Ordering::Relaxed, Ordering::Relaxed);
+          clonned_progress_bar.inc(1);
+          return ConnectionOutcome { connect_time: None, status: connect_error(&e).to_string(), end: None, lifetime: Duration::from_secs(0), bytes: 0 };
+        },
+        Err(_) => {
+          let open = clonned_open_connections.load(Ordering::Relaxed);
+          let _ = clonned_first_failure.compare_exchange(u64::MAX, open, Ordering::Relaxed, Ordering::Relaxed);
+          clonned_progress_bar.inc(1);
+          return ConnectionOutcome { connect_time: None, status: "Connect Timeout".to_string(), end: None, lifetime: Duration::from_secs(0), bytes: 0 };
+        },
+      };
+      let connect_time = connect_start_time.elapsed();
+
+      let open = clonned_open_connections.fetch_add(1, Ordering::Relaxed) + 1;
+      clonned_peak_open_connections.fetch_max(open, Ordering::Relaxed);
+
+      // stay idle and wait for the server to give up on us
+      let opened_at = Instant::now();
+      let mut bytes = 0;
+      let mut buffer = [0u8; 1024];
+      let hold = tokio::time::sleep(Duration::from_secs(clonned_config.hold));
+      tokio::pin!(hold);
+
+      let end = loop {
+        tokio::select! {
+          _ = &mut hold => break ConnectionEnd::Held,
+          read = connection.read(&mut buffer) => {
+            match read {
+              Ok(0) => break ConnectionEnd::Closed,
+              Ok(read) => bytes += read as u64,
+              Err(e) if e.kind() == io::ErrorKind::ConnectionReset => break ConnectionEnd::Reset,
+              Err(_) => break ConnectionEnd::Closed,
+            }
+          },
+        }
+      };
+      let lifetime = opened_at.elapsed();
+
+      clonned_open_connections.fetch_sub(1, Ordering::Relaxed);
+      clonned_progress_bar.inc(1);
+
+      let status = match end {
+        ConnectionEnd::Held => "Connected",
+        ConnectionEnd::Closed => "Closed By Server",
+        ConnectionEnd::Reset => "Reset By Server",
+      };
+
+      ConnectionOutcome { connect_time: Some(connect_time), status: status.to_string(), end: Some(end), lifetime, bytes }
+    });
+    handles.push(handle);
+  }
+
+  for handle in handles {
+    match handle.await {
+      Ok(outcome) => {
+        match outcome.connect_time {
+          Some(connect_time) => {
+            success_counter += 1;
+            latencies.push(connect_time);
+          },
+          None => error_counter += 1,
+        }
+
+        match outcome.end {
+          Some(ConnectionEnd::Held) => report.held_until_end += 1,
+          Some(ConnectionEnd::Closed) => {
+            report.closed_by_server += 1;
+            lifetimes.push(outcome.lifetime);
+          },
+          Some(ConnectionEnd::Reset) => {
+            report.reset_by_server += 1;
+            lifetimes.push(outcome.lifetime);
+          },
+          None => {},
+        }
+
+        total_bytes.fetch_add(outcome.bytes, Ordering::Relaxed);
+        *status_codes.entry(outcome.status).or_insert(0) += 1;
+      },
+      Err(_) => {
+        error_counter += 1;
+        *status_codes.entry("Panic Error".to_string()).or_insert(0) += 1;
+      }
+    }
+  }
+
+  let duration = start_time.elapsed();
+  latencies.sort();
+
+  report.peak_open_connections = peak_open_connections.load(Ordering::Relaxed);
+  let first_failure = first_failure.load(Ordering::Relaxed);
+  if first_failure != u64::MAX {
+    report.open_connections_at_first_failure = Some(first_failure);
+  }
+
+  if !lifetimes.is_empty() {
+    let percentiles = calculate_percentiles(&lifetimes);
+    let total: Duration = lifetimes.iter().sum();
+    report.min_idle_lifetime_ms = lifetimes.iter().min().map(|lifetime| lifetime.as_secs_f64() * 1000.0).unwrap_or(0.0);
+    report.avg_idle_lifetime_ms = total.as_secs_f64() * 1000.0 / lifetimes.len() as f64;
+    report.p50_idle_lifetime_ms = percentiles["p50"];
+  }
+
+  Ok(LoadResult {
+    latencies,
+    status_codes,
+    success_counter,
+    error_counter,
+    duration,
+    total_bytes,
+    tcp: Some(report),
+    ..Default::default()
+  })
+}
+
+pub fn connect_error(error: &io::Error) -> &'static str {
+  match error.kind() {
+    io::ErrorKind::ConnectionRefused => "Connection Refused",
+    io::ErrorKind::ConnectionReset => "Reset By Server",
+    io::ErrorKind::TimedOut => "Connect Timeout",
+    io::ErrorKind::InvalidData => "TLS Handshake Error",
+    _ => "Connect Error",
+  }
+}
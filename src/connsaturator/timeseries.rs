@@ -0,0 +1,203 @@
+use crate::connsaturator::Config;
+
+use serde::Serialize;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Duration;
+
+pub fn validate(config: &Config) -> Result<(), String> {
+  match Duration::try_from_secs_f64(config.interval) {
+    Ok(interval) if interval >= Duration::from_millis(1) => Ok(()),
+    _ => Err(format!("Invalid --interval {}, expected at least 0.001 seconds", config.interval)),
+  }
+}
+
+// requests of a single interval of the run
+#[derive(Clone, Debug, Default)]
+pub struct Bucket {
+  pub sent: u64,
+  pub completed: u64,
+  pub successes: u64,
+  pub errors: u64,
+  pub client_errors: u64,
+  pub server_errors: u64,
+  pub network_errors: u64,
+  pub other_errors: u64,
+  pub throttled: u64,
+  pub bytes: u64,
+  pub latencies: Vec<Duration>,
+  pub status_codes: HashMap<String, u64>,
+}
+
+impl Bucket {
+  pub fn avg_latency_ms(&self) -> f64 {
+    if self.latencies.is_empty() {
+      return 0.0;
+    }
+    self.latencies.iter().map(Duration::as_secs_f64).sum::<f64>() * 1000.0 / self.latencies.len() as f64
+  }
+
+  pub fn percentile_ms(&self, percentile: usize) -> f64 {
+    let mut latencies = self.latencies.clone();
+    latencies.sort();
+    latencies.get(latencies.len() * percentile / 100).map(|latency| latency.as_secs_f64() * 1000.0).unwrap_or_default()
+  }
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct TimeSeriesEntry {
+  pub start_secs: f64,
+  pub sent: u64,
+  pub completed: u64,
+  pub successes: u64,
+  pub errors: u64,
+  pub client_errors: u64,
+  pub server_errors: u64,
+  pub network_errors: u64,
+  pub other_errors: u64,
+  pub throttled: u64,
+  pub rps: f64,
+  pub bytes: u64,
+  pub avg_latency_ms: f64,
+  pub p50_latency_ms: f64,
+  pub p90_latency_ms: f64,
+  pub p95_latency_ms: f64,
+  pub p99_latency_ms: f64,
+}
+
+// buckets of --interval, requests are counted in the interval they were sent and responses in the one they completed
+#[derive(Clone, Debug, Default)]
+pub struct TimeSeries {
+  interval: Duration,
+  buckets: Vec<Bucket>,
+}
+
+impl TimeSeries {
+  pub fn new(interval: Duration) -> Self {
+    TimeSeries { interval: interval.max(Duration::from_millis(1)), buckets: Vec::new() }
+  }
+
+  pub fn interval(&self) -> Duration {
+    self.interval
+  }
+
+  pub fn sent(&mut self, at: Duration) {
+    self.bucket(at).sent += 1;
+  }
+
+  // `status` is the status line, or the kind of network error when there was no response
+  pub fn completed(&mut self, at: Duration, status: &str, latency: Option<Duration>, bytes: Option<u64>, success: bool) {
+    let bucket = self.bucket(at);
+    bucket.completed += 1;
+    if success {
+      bucket.successes += 1;
+    } else {
+      bucket.errors += 1;
+      match status.chars().next() {
+        Some('4') => bucket.client_errors += 1,
+        Some('5') => bucket.server_errors += 1,
+        Some(c) if c.is_ascii_digit() => bucket.other_errors += 1,
+        _ => bucket.network_errors += 1,
+      }
+    }
+    if status.starts_with("429") {
+      bucket.throttled += 1;
+    }
+    *bucket.status_codes.entry(status.to_string()).or_insert(0) += 1;
+    bucket.bytes += bytes.unwrap_or_default();
+    if let Some(latency) = latency {
+      bucket.latencies.push(latency);
+    }
+  }
+
+  pub fn buckets(&self) -> &[Bucket] {
+    &self.buckets
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.buckets.is_empty()
+  }
+
+  // start of a bucket in seconds since the start of the run
+  pub fn start_secs(&self, index: usize) -> f64 {
+    index as f64 * self.interval.as_secs_f64()
+  }
+
+  pub fn entry(&self, index: usize) -> TimeSeriesEntry {
+    let bucket = &self.buckets[index];
+    TimeSeriesEntry {
+      start_secs: self.start_secs(index),
+      sent: bucket.sent,
+      completed: bucket.completed,
+      successes: bucket.successes,
+      errors: bucket.errors,
+      client_errors: bucket.client_errors,
+      server_errors: bucket.server_errors,
+      network_errors: bucket.network_errors,
+      other_errors: bucket.other_errors,
+      throttled: bucket.throttled,
+      rps: bucket.completed as f64 / self.interval.as_secs_f64(),
+      bytes: bucket.bytes,
+      avg_latency_ms: bucket.avg_latency_ms(),
+      p50_latency_ms: bucket.percentile_ms(50),
+      p90_latency_ms: bucket.percentile_ms(90),
+      p95_latency_ms: bucket.percentile_ms(95),
+      p99_latency_ms: bucket.percentile_ms(99),
+    }
+  }
+
+  pub fn entries(&self) -> Vec<TimeSeriesEntry> {
+    (0..self.buckets.len()).map(|index| self.entry(index)).collect()
+  }
+
+  // intervals that are over `elapsed` after the start of the run and will not change anymore
+  pub fn closed(&self, elapsed: Duration) -> usize {
+    ((elapsed.as_secs_f64() / self.interval.as_secs_f64()) as usize).min(self.buckets.len())
+  }
+
+  pub fn header() -> String {
+    format!("{:>8} {:>8} {:>8} {:>6} {:>6} {:>6} {:>6} {:>6} {:>10} {:>9} {:>9} {:>9} {:>9}",
+      "Time s", "RPS", "OK", "4xx", "429", "5xx", "Net", "Other", "KB", "p50 ms", "p95 ms", "p99 ms", "Avg ms")
+  }
+
+  pub fn row(entry: &TimeSeriesEntry) -> String {
+    format!("{:>8.1} {:>8.1} {:>8} {:>6} {:>6} {:>6} {:>6} {:>6} {:>10.1} {:>9.2} {:>9.2} {:>9.2} {:>9.2}",
+      entry.start_secs, entry.rps, entry.successes, entry.client_errors, entry.throttled, entry.server_errors, entry.network_errors, entry.other_errors,
+      entry.bytes as f64 / 1024.0, entry.p50_latency_ms, entry.p95_latency_ms, entry.p99_latency_ms, entry.avg_latency_ms)
+  }
+
+  pub fn save_json(&self, now: &str) {
+    let json = serde_json::to_string_pretty(&self.entries()).unwrap();
+
+    let filename = format!("timeseries_{}.json", now);
+
+    let mut file = std::fs::File::create(filename).unwrap();
+    file.write_all(json.as_bytes()).unwrap();
+  }
+
+  pub fn save_csv(&self, now: &str) {
+    let mut csv = String::from("start_secs,sent,completed,successes,errors,client_errors,server_errors,network_errors,other_errors,throttled,rps,bytes,avg_latency_ms,p50,p90,p95,p99\n");
+
+    for entry in self.entries() {
+      csv.push_str(&format!(
+        "{:.3},{},{},{},{},{},{},{},{},{},{:.2},{},{:.2},{:.2},{:.2},{:.2},{:.2}\n",
+        entry.start_secs, entry.sent, entry.completed, entry.successes, entry.errors, entry.client_errors, entry.server_errors, entry.network_errors, entry.other_errors,
+        entry.throttled, entry.rps, entry.bytes, entry.avg_latency_ms, entry.p50_latency_ms, entry.p90_latency_ms, entry.p95_latency_ms, entry.p99_latency_ms,
+      ));
+    }
+
+    let filename = format!("timeseries_{}.csv", now);
+
+    let mut file = std::fs::File::create(filename).unwrap();
+    file.write_all(csv.as_bytes()).unwrap();
+  }
+
+  fn bucket(&mut self, at: Duration) -> &mut Bucket {
+    let index = (at.as_secs_f64() / self.interval.as_secs_f64()) as usize;
+    if self.buckets.len() <= index {
+      self.buckets.resize_with(index + 1, Bucket::default);
+    }
+    &mut self.buckets[index]
+  }
+}
@@ -0,0 +1,172 @@
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::pki_types::pem::PemObject;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use p12_keystore::{KeyStore, Pkcs12ImportPolicy};
+
+use crate::connsaturator::Config;
+
+use std::fs;
+use std::sync::Arc;
+
+// client certificate chain and private key presented for mutual TLS
+pub struct ClientIdentity {
+  pub certs: Vec<CertificateDer<'static>>,
+  pub key: PrivateKeyDer<'static>,
+}
+
+impl ClientIdentity {
+  // PEM certificate (with the key in the same file unless `key` is given) or a PKCS#12 archive
+  pub fn load(cert: &str, key: Option<&str>, password: Option<&str>) -> Result<Self, String> {
+    let data = fs::read(cert).map_err(|e| format!("Unable to read client certificate {}: {}", cert, e))?;
+
+    if !is_pem(&data) {
+      if key.is_some() {
+        return Err(format!("Client certificate {} is not PEM, --key can only be used with PEM certificates", cert));
+      }
+      return Self::from_pkcs12(cert, &data, password.unwrap_or(""));
+    }
+
+    let certs = CertificateDer::pem_slice_iter(&data)
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|e| format!("Invalid client certificate {}: {}", cert, e))?;
+    if certs.is_empty() {
+      return Err(format!("Invalid client certificate {}: no certificate found", cert));
+    }
+
+    let key = match key {
+      Some(key) => {
+        let data = fs::read(key).map_err(|e| format!("Unable to read private key {}: {}", key, e))?;
+        PrivateKeyDer::from_pem_slice(&data).map_err(|e| format!("Invalid private key {}: {}", key, e))?
+      },
+      None => PrivateKeyDer::from_pem_slice(&data).map_err(|_| format!("No private key found in {}, use --key", cert))?,
+    };
+
+    Ok(ClientIdentity { certs, key })
+  }
+
+  fn from_pkcs12(path: &str, data: &[u8], password: &str) -> Result<Self, String> {
+    let keystore = KeyStore::from_pkcs12(data, password, Pkcs12ImportPolicy::Strict)
+      .map_err(|e| format!("Invalid PKCS#12 certificate {}: {}", path, e))?;
+    let (_, chain) = keystore.private_key_chain()
+      .ok_or(format!("Invalid PKCS#12 certificate {}: no private key found", path))?;
+
+    let certs = chain.certs().iter().map(|cert| CertificateDer::from(cert.as_der().to_vec())).collect();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(chain.key().as_der().to_vec()));
+
+    Ok(ClientIdentity { certs, key })
+  }
+
+  pub fn cert_pem(&self) -> String {
+    certs_pem(&self.certs)
+  }
+
+  pub fn key_pem(&self) -> String {
+    let label = match &self.key {
+      PrivateKeyDer::Pkcs1(_) => "RSA PRIVATE KEY",
+      PrivateKeyDer::Sec1(_) => "EC PRIVATE KEY",
+      _ => "PRIVATE KEY",
+    };
+    pem_encode(label, self.key.secret_der())
+  }
+}
+
+pub fn client_identity(config: &Config) -> Result<Option<ClientIdentity>, String> {
+  match (&config.cert, &config.key) {
+    (Some(cert), key) => ClientIdentity::load(cert, key.as_deref(), config.cert_password.as_deref()).map(Some),
+    (None, Some(_)) => Err("--key requires --cert".to_string()),
+    (None, None) => Ok(None),
+  }
+}
+
+// extra CA certificates trusted on top of the built-in roots
+pub fn ca_bundle(config: &Config) -> Result<Vec<CertificateDer<'static>>, String> {
+  let Some(path) = &config.cacert else {
+    return Ok(Vec::new());
+  };
+
+  let data = fs::read(path).map_err(|e| format!("Unable to read CA bundle {}: {}", path, e))?;
+  let certs = CertificateDer::pem_slice_iter(&data)
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|e| format!("Invalid CA bundle {}: {}", path, e))?;
+  if certs.is_empty() {
+    return Err(format!("Invalid CA bundle {}: no certificate found", path));
+  }
+
+  Ok(certs)
+}
+
+// rustls configuration for the modes that open connections without reqwest
+pub fn client_config(config: &Config) -> Result<Arc<ClientConfig>, String> {
+  let provider = Arc::new(rustls::crypto::ring::default_provider());
+  let builder = ClientConfig::builder_with_provider(provider.clone())
+    .with_safe_default_protocol_versions()
+    .map_err(|e| format!("Invalid TLS configuration: {}", e))?;
+
+  let builder = if config.insecure {
+    builder
+      .dangerous()
+      .with_custom_certificate_verifier(Arc::new(NoCertificateVerification { provider }))
+  } else {
+    let mut roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+    for cert in ca_bundle(config)? {
+      roots.add(cert).map_err(|e| format!("Invalid CA certificate: {}", e))?;
+    }
+    builder.with_root_certificates(roots)
+  };
+
+  let tls_config = match client_identity(config)? {
+    Some(identity) => builder
+      .with_client_auth_cert(identity.certs, identity.key)
+      .map_err(|e| format!("Invalid client certificate: {}", e))?,
+    None => builder.with_no_client_auth(),
+  };
+
+  Ok(Arc::new(tls_config))
+}
+
+pub fn certs_pem(certs: &[CertificateDer<'_>]) -> String {
+  certs.iter().map(|cert| pem_encode("CERTIFICATE", cert)).collect()
+}
+
+fn is_pem(data: &[u8]) -> bool {
+  data.windows(10).any(|window| window == b"-----BEGIN")
+}
+
+fn pem_encode(label: &str, der: &[u8]) -> String {
+  let encoded = STANDARD.encode(der);
+  let mut pem = format!("-----BEGIN {}-----\n", label);
+  for line in encoded.as_bytes().chunks(64) {
+    pem.push_str(std::str::from_utf8(line).unwrap());
+    pem.push('\n');
+  }
+  pem.push_str(&format!("-----END {}-----\n", label));
+  pem
+}
+
+// equivalent of reqwest's danger_accept_invalid_certs
+#[derive(Debug)]
+struct NoCertificateVerification {
+  provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for NoCertificateVerification {
+  fn verify_server_cert(&self, _end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], _server_name: &ServerName<'_>, _ocsp_response: &[u8], _now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+    Ok(ServerCertVerified::assertion())
+  }
+
+  fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+    verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+  }
+
+  fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+    verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+  }
+
+  fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+    self.provider.signature_verification_algorithms.supported_schemes()
+  }
+}
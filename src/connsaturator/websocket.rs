@@ -0,0 +1,253 @@
+use crate::connsaturator::Config;
+use crate::connsaturator::LoadResult;
+use crate::connsaturator::RequestContext;
+use crate::connsaturator::net::{self, ConnectOptions, Target};
+use crate::connsaturator::requestbuilder;
+use crate::connsaturator::resolve;
+use crate::connsaturator::saturator::calculate_percentiles;
+use crate::connsaturator::tls;
+
+use futures_util::{SinkExt, StreamExt};
+use indicatif::ProgressBar;
+use rustls::ClientConfig;
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tokio::time::Interval;
+use tokio_tungstenite::Connector;
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct WebSocketReport {
+  pub connections_opened: u64,
+  pub dropped_connections: u64,
+  pub messages_sent: u64,
+  pub messages_received: u64,
+  pub avg_rtt_ms: f64,
+  pub p50_rtt_ms: f64,
+  pub p95_rtt_ms: f64,
+  pub p99_rtt_ms: f64,
+}
+
+impl WebSocketReport {
+  pub fn print(&self) {
+    println!("\nWebSocket:");
+    println!("{}", "-".repeat(60));
+    println!("{:<35} {}", "Connections opened:", self.connections_opened);
+    println!("{:<35} {}", "Dropped connections:", self.dropped_connections);
+    println!("{:<35} {}", "Messages sent:", self.messages_sent);
+    println!("{:<35} {}", "Messages received:", self.messages_received);
+    if self.messages_received > 0 && self.messages_sent > 0 {
+      println!("{:<35} {:.2} ms", "Average round-trip:", self.avg_rtt_ms);
+      println!("{:<35} {:.2} ms", "p50 round-trip:", self.p50_rtt_ms);
+      println!("{:<35} {:.2} ms", "p95 round-trip:", self.p95_rtt_ms);
+      println!("{:<35} {:.2} ms", "p99 round-trip:", self.p99_rtt_ms);
+    }
+  }
+}
+
+struct ConnectionOutcome {
+  connect_time: Option<Duration>,
+  status: String,
+  dropped: bool,
+  round_trips: Vec<Duration>,
+  sent: u64,
+  received: u64,
+  bytes: u64,
+}
+
+impl ConnectionOutcome {
+  fn failed(status: String) -> Self {
+    ConnectionOutcome { connect_time: None, status, dropped: false, round_trips: Vec::new(), sent: 0, received: 0, bytes: 0 }
+  }
+}
+
+// opens `requests` connections, at most `concurrency` of them at the same time, holding each one for `hold` seconds
+pub async fn execute_connections(url: &str, config: &Config, progress_bar: &ProgressBar) -> Result<LoadResult, String> {
+  let mut latencies = Vec::new();
+  let mut status_codes = HashMap::new();
+  let mut success_counter = 0;
+  let mut error_counter = 0;
+  let total_bytes = AtomicU64::new(0);
+  let mut round_trips = Vec::new();
+  let mut report = WebSocketReport::default();
+
+  let semaphore = Arc::new(Semaphore::new(config.concurrency));
+  let config = Arc::new(config.clone());
+  let tls_config = Some(tls::client_config(&config)?);
+
+  let start_time = Instant::now();
+
+  let mut handles = Vec::new();
+
+  for iteration in 0..config.requests {
+    let context = RequestContext::new(iteration as u64, &config);
+    let clonned_url = requestbuilder::create_url(url, &config, &context);
+    let clonned_config = Arc::clone(&config);
+    let clonned_tls_config = tls_config.clone();
+    let clonned_progress_bar = progress_bar.clone();
+
+    let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
+
+    let handle = tokio::spawn(async move {
+      let _permit = permit;
+      let outcome = open_connection(clonned_url, clonned_config, clonned_tls_config, context).await;
+      clonned_progress_bar.inc(1);
+      outcome
+    });
+    handles.push(handle);
+  }
+
+  for handle in handles {
+    match handle.await {
+      Ok(outcome) => {
+        if let Some(connect_time) = outcome.connect_time {
+          report.connections_opened += 1;
+          latencies.push(connect_time);
+        }
+
+        if outcome.connect_time.is_some() && !outcome.dropped {
+          success_counter += 1;
+        } else {
+          error_counter += 1;
+        }
+
+        if outcome.dropped {
+          report.dropped_connections += 1;
+        }
+
+        report.messages_sent += outcome.sent;
+        report.messages_received += outcome.received;
+        round_trips.extend(outcome.round_trips);
+        total_bytes.fetch_add(outcome.bytes, Ordering::Relaxed);
+
+        *status_codes.entry(outcome.status).or_insert(0) += 1;
+      },
+      Err(_) => {
+        error_counter += 1;
+        *status_codes.entry("Panic Error".to_string()).or_insert(0) += 1;
+      }
+    }
+  }
+
+  let duration = start_time.elapsed();
+  latencies.sort();
+
+  if !round_trips.is_empty() {
+    let percentiles = calculate_percentiles(&round_trips);
+    let total: Duration = round_trips.iter().sum();
+    report.avg_rtt_ms = total.as_secs_f64() * 1000.0 / round_trips.len() as f64;
+    report.p50_rtt_ms = percentiles["p50"];
+    report.p95_rtt_ms = percentiles["p95"];
+    report.p99_rtt_ms = percentiles["p99"];
+  }
+
+  Ok(LoadResult {
+    latencies,
+    status_codes,
+    success_counter,
+    error_counter,
+    duration,
+    total_bytes,
+    websocket: Some(report),
+    ..Default::default()
+  })
+}
+
+async fn open_connection(url: String, config: Arc<Config>, tls_config: Option<Arc<ClientConfig>>, context: RequestContext) -> ConnectionOutcome {
+  let mut request = match url.as_str().into_client_request() {
+    Ok(request) => request,
+    Err(_) => return ConnectionOutcome::failed("Invalid Request".to_string()),
+  };
+  request.headers_mut().extend(requestbuilder::create_headers(&config, &context));
+
+  let Ok(target) = Target::parse_target(&url) else {
+    return ConnectionOutcome::failed("Invalid Request".to_string());
+  };
+  let options = ConnectOptions {
+    address: resolve::address(&config, &target.host, target.port, &context),
+    source: net::source_address(&config, &context),
+    ..Default::default()
+  };
+
+  let connect_start_time = Instant::now();
+  let connection = tokio::time::timeout(Duration::from_secs(config.timeout), async {
+    let stream = net::connect_tcp(&target, &options).await.map_err(Error::Io)?;
+    tokio_tungstenite::client_async_tls_with_config(request, stream, None, tls_config.map(Connector::Rustls)).await
+  }).await;
+
+  let stream = match connection {
+    Ok(Ok((stream, _))) => stream,
+    Ok(Err(Error::Http(response))) => return ConnectionOutcome::failed(format!("Handshake Rejected: {}", response.status())),
+    Ok(Err(_)) => return ConnectionOutcome::failed("Connect Error".to_string()),
+    Err(_) => return ConnectionOutcome::failed("Connect Timeout".to_string()),
+  };
+
+  let mut outcome = ConnectionOutcome::failed("101 Switching Protocols".to_string());
+  outcome.connect_time = Some(connect_start_time.elapsed());
+
+  let (mut write, mut read) = stream.split();
+  let mut ticker = config.message_rate.filter(|rate| *rate > 0.0).map(|rate| tokio::time::interval(Duration::from_secs_f64(1.0 / rate)));
+  let message = config.message.clone().unwrap_or_else(|| "ping".to_string());
+  let mut pending: VecDeque<Instant> = VecDeque::new();
+
+  let hold = tokio::time::sleep(Duration::from_secs(config.hold));
+  tokio::pin!(hold);
+
+  loop {
+    tokio::select! {
+      _ = &mut hold => break,
+      _ = next_tick(&mut ticker) => {
+        if write.send(Message::text(message.clone())).await.is_err() {
+          outcome.dropped = true;
+          break;
+        }
+        pending.push_back(Instant::now());
+        outcome.sent += 1;
+      },
+      incoming = read.next() => {
+        match incoming {
+          Some(Ok(Message::Text(text))) => {
+            outcome.bytes += text.len() as u64;
+            outcome.received += 1;
+            if let Some(sent_at) = pending.pop_front() {
+              outcome.round_trips.push(sent_at.elapsed());
+            }
+          },
+          Some(Ok(Message::Binary(data))) => {
+            outcome.bytes += data.len() as u64;
+            outcome.received += 1;
+            if let Some(sent_at) = pending.pop_front() {
+              outcome.round_trips.push(sent_at.elapsed());
+            }
+          },
+          Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+            outcome.dropped = true;
+            break;
+          },
+          Some(Ok(_)) => {},
+        }
+      },
+    }
+  }
+
+  if outcome.dropped {
+    outcome.status = "Dropped By Server".to_string();
+  } else {
+    let _ = write.send(Message::Close(None)).await;
+  }
+
+  outcome
+}
+
+async fn next_tick(ticker: &mut Option<Interval>) {
+  match ticker {
+    Some(ticker) => { ticker.tick().await; },
+    None => std::future::pending::<()>().await,
+  }
+}
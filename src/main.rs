@@ -1,8 +1,8 @@
 
 mod connsaturator;
-use connsaturator::{Config, HttpMethods, ConnSaturator, AuthMethods, CustomHeaders };
+use connsaturator::{Config, HttpMethods, ConnSaturator, AuthMethods, CustomHeaders, TestMode, SlowMode, ApiKeyPlacement, CookieMode, ResolveOverride, ClientIpRange, IdentityHeader, ExpectedLimit, RetryCondition, BackoffStrategy, LoadShape, Arrival, ThinkTime, signing, jwt };
 use clap::Parser;
-use reqwest::header::HeaderValue;
+use std::net::IpAddr;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "A simple connection saturator tester", long_about = None)]
@@ -23,7 +23,7 @@ struct Cli {
      #[arg(short, long, value_enum, default_value_t = HttpMethods::Get)]
     pub method: HttpMethods,
 
-    /// Authentication method (Bearer, OAuth2, APIKey, Basic)
+    /// Authentication method (Bearer, OAuth2, APIKey key or @keys_file, Basic, HMAC key_id:secret, SigV4 access_key:secret_key[:session_token], JWT ALG:key_file)
     #[arg(long, value_parser = AuthMethods::parse_auth)]
     pub token: Option<AuthMethods>,
 
@@ -31,7 +31,7 @@ struct Cli {
     #[arg(long, value_parser = CustomHeaders::parse_header)]
     pub header: Option<CustomHeaders>,
 
-    /// Body of the request
+    /// Body of the request (JSON-encoded message in grpc mode)
     #[arg(short, long)]
     pub body: Option<String>,
     
@@ -58,6 +58,250 @@ struct Cli {
     /// Warmup requests (Default: 0)
     #[arg(long, short = 'w', default_value_t = 0)]
     pub warmup: usize,
+
+    /// Test mode
+    #[arg(long, value_enum, default_value_t = TestMode::Http)]
+    pub mode: TestMode,
+
+    /// Seconds each connection is held open (ws, stream, tcp and slow modes)
+    #[arg(long, default_value_t = 10)]
+    pub hold: u64,
+
+    /// Message sent over each WebSocket connection (Default: ping)
+    #[arg(long)]
+    pub message: Option<String>,
+
+    /// Messages per second sent over each WebSocket connection (Default: none)
+    #[arg(long = "message-rate")]
+    pub message_rate: Option<f64>,
+
+    /// gRPC method to call, e.g. package.Service/Method (grpc mode)
+    #[arg(long = "grpc-method")]
+    pub grpc_method: Option<String>,
+
+    /// Protobuf descriptor set file (grpc mode, Default: server reflection)
+    #[arg(long = "proto-set")]
+    pub proto_set: Option<String>,
+
+    /// Slow client behaviour (slow mode)
+    #[arg(long = "slow-mode", value_enum, default_value_t = SlowMode::Headers)]
+    pub slow_mode: SlowMode,
+
+    /// Milliseconds between slow writes or reads (slow mode)
+    #[arg(long = "slow-interval", default_value_t = 1000)]
+    pub slow_interval: u64,
+
+    /// Bytes written or read per interval (slow mode)
+    #[arg(long = "slow-chunk", default_value_t = 1)]
+    pub slow_chunk: usize,
+
+    /// Open a new connection for every request instead of reusing pooled ones (http mode)
+    #[arg(long = "no-keepalive", alias = "new-connection-per-request", default_value_t = false)]
+    pub no_keepalive: bool,
+
+    /// Disable TLS session resumption so every handshake is a full one (handshake mode)
+    #[arg(long = "no-resumption", default_value_t = false)]
+    pub no_resumption: bool,
+
+    /// Client certificate for mutual TLS, PEM or PKCS#12 (.p12/.pfx)
+    #[arg(long)]
+    pub cert: Option<String>,
+
+    /// PEM private key for --cert (Default: read from the certificate file)
+    #[arg(long)]
+    pub key: Option<String>,
+
+    /// Password of a PKCS#12 client certificate
+    #[arg(long = "cert-password")]
+    pub cert_password: Option<String>,
+
+    /// PEM CA bundle trusted in addition to the built-in roots
+    #[arg(long)]
+    pub cacert: Option<String>,
+
+    /// Header carrying the HMAC signature (hmac auth)
+    #[arg(long = "sign-header", default_value = "X-Signature")]
+    pub sign_header: String,
+
+    /// Canonical string signed with HMAC, placeholders: {method} {path} {query} {host} {timestamp} {body} {body_sha256} {header:NAME} (hmac auth)
+    #[arg(long = "sign-template", default_value = signing::DEFAULT_HMAC_TEMPLATE)]
+    pub sign_template: String,
+
+    /// AWS region for SigV4 signing (sigv4 auth)
+    #[arg(long = "aws-region")]
+    pub aws_region: Option<String>,
+
+    /// AWS service for SigV4 signing (sigv4 auth)
+    #[arg(long = "aws-service", default_value = "execute-api")]
+    pub aws_service: String,
+
+    /// Number of distinct virtual users requests are spread across (Default: concurrency)
+    #[arg(long = "virtual-users")]
+    pub virtual_users: Option<usize>,
+
+    /// Additional JWT claims as a JSON object (jwt auth)
+    #[arg(long = "jwt-claims", value_parser = jwt::parse_claims)]
+    pub jwt_claims: Option<serde_json::Map<String, serde_json::Value>>,
+
+    /// Seconds until minted JWTs expire (jwt auth)
+    #[arg(long = "jwt-expiry", default_value_t = 300)]
+    pub jwt_expiry: u64,
+
+    /// JWT subject, {vu} is replaced by the virtual user number (jwt auth)
+    #[arg(long = "jwt-subject", default_value = "user-{vu}")]
+    pub jwt_subject: String,
+
+    /// Where the API key is sent (apikey auth)
+    #[arg(long = "api-key-in", value_enum, default_value_t = ApiKeyPlacement::Header)]
+    pub api_key_in: ApiKeyPlacement,
+
+    /// Header, query parameter or cookie name of the API key (apikey auth, Default: X-API-Key or api_key)
+    #[arg(long = "api-key-name")]
+    pub api_key_name: Option<String>,
+
+    /// Keep cookies between requests in one shared jar or one jar per virtual user (http mode)
+    #[arg(long, value_enum)]
+    pub cookies: Option<CookieMode>,
+
+    /// Cookie preloaded into every jar as NAME=VALUE, can be repeated (enables a shared jar by default)
+    #[arg(long)]
+    pub cookie: Vec<String>,
+
+    /// Cookie counted as the session id (Default: names containing sess or ending in sid)
+    #[arg(long = "session-cookie")]
+    pub session_cookie: Option<String>,
+
+    /// Maximum number of redirects followed per request
+    #[arg(long = "max-redirects", default_value_t = 10)]
+    pub max_redirects: usize,
+
+    /// Do not follow redirects, same as --max-redirects 0
+    #[arg(long = "no-follow", default_value_t = false)]
+    pub no_follow: bool,
+
+    /// Proxy as http://, https:// or socks5:// URL with optional user:password@, can be repeated to rotate across virtual users (http and stream modes)
+    #[arg(long)]
+    pub proxy: Vec<String>,
+
+    /// Send requests for host:port to the given addresses instead of resolving it, as host:port:addr[,addr...], can be repeated
+    #[arg(long, value_parser = ResolveOverride::parse)]
+    pub resolve: Vec<ResolveOverride>,
+
+    /// Spread virtual users across every A/AAAA record of the target host
+    #[arg(long = "all-ips", default_value_t = false)]
+    pub all_ips: bool,
+
+    /// Local address outgoing connections are bound to, can be repeated to round-robin virtual users across several client IPs
+    #[arg(long = "source-address")]
+    pub source_address: Vec<IpAddr>,
+
+    /// CIDR range each virtual user gets a synthetic client IP from, sent in the forwarding headers (e.g. 10.0.0.0/16)
+    #[arg(long = "client-ip-range", value_parser = ClientIpRange::parse)]
+    pub client_ip_range: Option<ClientIpRange>,
+
+    /// Header the synthetic client IP is sent in, can be repeated (Default: all of them)
+    #[arg(long = "client-ip-header", value_enum)]
+    pub client_ip_header: Vec<IdentityHeader>,
+
+    /// Rate limit the target should enforce as requests/window (e.g. 100/min, 10/s, 50/30s), checked against the observed 429 responses
+    #[arg(long = "expected-limit", value_parser = ExpectedLimit::parse)]
+    pub expected_limit: Option<ExpectedLimit>,
+
+    /// Behave like a well-behaved client: on 429 or 503 wait for Retry-After, or back off exponentially with jitter, then retry (http mode)
+    #[arg(long, default_value_t = false)]
+    pub polite: bool,
+
+    /// Retries after a failed attempt (Default: 0, or 5 with --polite)
+    #[arg(long)]
+    pub retries: Option<usize>,
+
+    /// Failures that are retried, comma separated: connect, timeout, 5xx or status codes (Default: connect,timeout,5xx)
+    #[arg(long = "retry-on", value_delimiter = ',', value_parser = RetryCondition::parse)]
+    pub retry_on: Vec<RetryCondition>,
+
+    /// Wait between attempts when the response has no Retry-After
+    #[arg(long, value_enum, default_value_t = BackoffStrategy::ExponentialJitter)]
+    pub backoff: BackoffStrategy,
+
+    /// Base delay of the exponential back-off in milliseconds
+    #[arg(long = "backoff-base", default_value_t = 500)]
+    pub backoff_base: u64,
+
+    /// Longest single back-off in milliseconds when the response has no Retry-After
+    #[arg(long = "backoff-max", default_value_t = 30000)]
+    pub backoff_max: u64,
+
+    /// Longest Retry-After wait in milliseconds (Default: as long as the server asks)
+    #[arg(long = "retry-after-max")]
+    pub retry_after_max: Option<u64>,
+
+    /// Failure percentage of a 1 s window that marks the target as failing (recovery mode)
+    #[arg(long = "failure-threshold", default_value_t = 50.0)]
+    pub failure_threshold: f64,
+
+    /// Probes per second sent once the load stops (recovery mode)
+    #[arg(long = "probe-rate", default_value_t = 1.0)]
+    pub probe_rate: f64,
+
+    /// Seconds of consecutive successful probes that count as recovered (recovery mode)
+    #[arg(long = "recovery-window", default_value_t = 5)]
+    pub recovery_window: u64,
+
+    /// Seconds to keep probing before giving up on recovery (recovery mode)
+    #[arg(long = "recovery-timeout", default_value_t = 120)]
+    pub recovery_timeout: u64,
+
+    /// Requests per second to send, open loop, instead of as fast as the concurrency allows (http and grpc modes)
+    #[arg(long)]
+    pub rate: Option<f64>,
+
+    /// How the request rate changes over the run, requires --rate
+    #[arg(long, value_enum, default_value_t = LoadShape::Constant)]
+    pub shape: LoadShape,
+
+    /// Rate multiplier at the top of a burst, spike or wave
+    #[arg(long, default_value_t = 10.0)]
+    pub peak: f64,
+
+    /// Seconds between bursts, or the length of a sine or square wave
+    #[arg(long, default_value_t = 60)]
+    pub period: u64,
+
+    /// Seconds each burst or spike lasts
+    #[arg(long = "burst-duration", default_value_t = 5)]
+    pub burst_duration: u64,
+
+    /// Seconds after the start of the run the spike begins
+    #[arg(long = "spike-at", default_value_t = 10)]
+    pub spike_at: u64,
+
+    /// Spread of the gaps between requests sent at --rate: constant, poisson, uniform[:jitter] or empirical:file (one gap in ms per line, scaled to the rate)
+    #[arg(long, value_parser = Arrival::parse, default_value = "constant")]
+    pub arrival: Arrival,
+
+    /// Seed of the random arrivals, runs with the same seed send requests at the same times (Default: random, printed in the report)
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Pause of each virtual user between a response and its next request, in ms: 1000, 500-1500 (uniform), normal:1000,200 or exp:1000 (http mode)
+    #[arg(long = "think-time", value_parser = ThinkTime::parse)]
+    pub think_time: Option<ThinkTime>,
+
+    /// Minimum milliseconds from the start of one iteration of a virtual user to the start of its next, think time included (http mode)
+    #[arg(long)]
+    pub pacing: Option<u64>,
+
+    /// Seconds per bucket of the time series, written to timeseries_*.csv and .json with --output (http mode)
+    #[arg(long, default_value_t = 1.0)]
+    pub interval: f64,
+
+    /// Print a row of the time series as each --interval closes, while the run is going
+    #[arg(long, default_value_t = false)]
+    pub rolling: bool,
+
+    /// Full screen live dashboard while the run is going: p pauses, r resumes, +/- change the concurrency and q aborts with a partial report (http mode)
+    #[arg(long, default_value_t = false)]
+    pub dashboard: bool,
 }
 
 #[tokio::main]
@@ -66,7 +310,21 @@ pub async fn main() {
     let arguments = Cli::parse();
 
     // initialize saturator
-    let config = Config {
+    let config = config(arguments);
+
+    // create saturator and run
+    match ConnSaturator::new(config) {
+        Ok(saturator) => {
+            saturator.run().await;
+        }
+        Err(e) => {
+            eprintln!("Error crítico al configurar el saturator: {}", e);
+        }
+    }
+}
+
+fn config(arguments: Cli) -> Config {
+    Config {
         url: arguments.url,
         requests: arguments.requests,
         concurrency: arguments.concurrency, 
@@ -80,17 +338,73 @@ pub async fn main() {
         insecure: arguments.insecure,
         output: arguments.output,
         warmup: arguments.warmup,
-    };
-
-    // create saturator and run
-    match ConnSaturator::new(config) {
-        Ok(saturator) => {
-            saturator.run().await;
-        }
-        Err(e) => {
-            eprintln!("Error crítico al configurar el saturator: {}", e);
-            return;
-        }
+        mode: arguments.mode,
+        hold: arguments.hold,
+        message: arguments.message,
+        message_rate: arguments.message_rate,
+        grpc_method: arguments.grpc_method,
+        proto_set: arguments.proto_set,
+        slow_mode: arguments.slow_mode,
+        slow_interval: arguments.slow_interval,
+        slow_chunk: arguments.slow_chunk,
+        no_keepalive: arguments.no_keepalive,
+        no_resumption: arguments.no_resumption,
+        cert: arguments.cert,
+        key: arguments.key,
+        cert_password: arguments.cert_password,
+        cacert: arguments.cacert,
+        sign_header: arguments.sign_header,
+        sign_template: arguments.sign_template,
+        aws_region: arguments.aws_region,
+        aws_service: arguments.aws_service,
+        virtual_users: arguments.virtual_users.unwrap_or(arguments.concurrency).max(1),
+        jwt_claims: arguments.jwt_claims.unwrap_or_default(),
+        jwt_expiry: arguments.jwt_expiry,
+        jwt_subject: arguments.jwt_subject,
+        api_key_in: arguments.api_key_in,
+        api_key_name: arguments.api_key_name,
+        cookies: arguments.cookies,
+        cookie: arguments.cookie,
+        session_cookie: arguments.session_cookie,
+        cookie_jars: None,
+        max_redirects: if arguments.no_follow { 0 } else { arguments.max_redirects },
+        proxy: arguments.proxy,
+        resolve: arguments.resolve,
+        all_ips: arguments.all_ips,
+        source_address: arguments.source_address,
+        client_ip_range: arguments.client_ip_range,
+        client_ip_header: arguments.client_ip_header,
+        expected_limit: arguments.expected_limit,
+        polite: arguments.polite,
+        retries: arguments.retries,
+        retry_on: arguments.retry_on,
+        backoff: arguments.backoff,
+        backoff_base: arguments.backoff_base,
+        backoff_max: arguments.backoff_max,
+        retry_after_max: arguments.retry_after_max,
+        failure_threshold: arguments.failure_threshold,
+        probe_rate: arguments.probe_rate,
+        recovery_window: arguments.recovery_window,
+        recovery_timeout: arguments.recovery_timeout,
+        rate: arguments.rate,
+        shape: arguments.shape,
+        peak: arguments.peak,
+        period: arguments.period,
+        burst_duration: arguments.burst_duration,
+        spike_at: arguments.spike_at,
+        arrival: arguments.arrival,
+        seed: arguments.seed,
+        think_time: arguments.think_time,
+        pacing: arguments.pacing,
+        interval: arguments.interval,
+        rolling: arguments.rolling,
+        dashboard: arguments.dashboard,
     }
 }
 
+// configuration as parsed from command line arguments, for unit tests
+#[cfg(test)]
+pub fn test_config(arguments: &[&str]) -> Config {
+    config(Cli::parse_from(["ConnSaturatorRS"].iter().chain(arguments)))
+}
+